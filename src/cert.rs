@@ -1,49 +1,851 @@
 use bollard::Docker;
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair};
+use serde_derive::{Deserialize, Serialize};
 use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use time::{Duration, OffsetDateTime};
+use x509_parser::extensions::GeneralName;
+use x509_parser::pem::Pem;
+use x509_parser::time::ASN1Time;
 
-use super::log_exit;
-use crate::docker::run_image;
+use crate::conf::{get_jinx_conf, JinxAcme, JinxConf};
+use crate::docker::{reload_jinx_proxy, run_image, run_image_to_completion};
+use crate::error::JinxError;
 use crate::file::get_jinx_files;
 use crate::file::JinxFiles;
+use crate::service::{JinxCustomCert, JinxService};
 
-pub async fn run_letsencrypt_container(client: Docker, jinx_files: &JinxFiles, domain: String) {
-    // certbot server ports
-    let ports = vec!["80:80/tcp", "443:443/tcp"];
+// container path of the dns plugin credentials
+const DNS_CREDENTIALS: &str = "/etc/jinx/dns-credentials.ini";
+
+// days a development certificate is valid, the most browsers accept
+const DEV_CERT_DAYS: i64 = 825;
+
+// requests a certificate covering every server name of the JinxService
+pub async fn run_letsencrypt_container(
+    client: Docker,
+    jinx_files: &JinxFiles,
+    jinx_service: &JinxService,
+) -> Result<(), JinxError> {
+    // get acme settings
+    let acme = get_jinx_conf()?.acme.unwrap_or_default();
+
+    let cert_name = jinx_service.get_cert_name();
+    let server_names = jinx_service.get_server_names();
+
+    // http-01 cannot prove control of a wildcard
+    if acme.dns_plugin.is_none() && server_names.iter().any(|s| s.starts_with("*.")) {
+        return Err(JinxError::InvalidConfig(format!(
+            "wildcard domains of {} need an acme dns_plugin",
+            jinx_service.name
+        )));
+    }
+
+    // certbot server ports, only the standalone http-01 server needs them
+    let ports = match acme.dns_plugin {
+        Some(_) => vec![],
+        None => vec!["80:80/tcp", "443:443/tcp"],
+    };
 
     // mount volumes
-    let conf = format!("{}:/etc/letsencrypt", jinx_files.letsencrypt_conf);
-    let www = format!("{}:/var/www/certbot", jinx_files.letsencrypt_www);
-    let volumes = vec![conf.as_str(), www.as_str()];
-
-    let std_domain = format!("-d {}", domain);
-    let www_domain = format!("-d www.{}", domain);
-
-    let cmds = vec![
-        "certonly",
-        "--register-unsafely-without-email",
-        "--agree-tos",
-        "--standalone",
-        std_domain.as_str(),
-        www_domain.as_str(),
+    let volumes = get_certbot_volumes(jinx_files, &acme);
+    let volumes = volumes.iter().map(|v| v.as_str()).collect();
+    let envs = acme.dns_envs.clone().unwrap_or_default();
+    let envs = envs.iter().map(|e| e.as_str()).collect();
+
+    let mut cmds = vec!["certonly".to_string()];
+    cmds.append(&mut get_account_args(&acme));
+    cmds.append(&mut get_challenge_args(&acme, &["--standalone"]));
+    cmds.push("--cert-name".to_string());
+    cmds.push(cert_name);
+    for server_name in server_names {
+        cmds.push("-d".to_string());
+        cmds.push(server_name);
+    }
+    let cmds = cmds.iter().map(|c| c.as_str()).collect();
+
+    run_image(
+        client,
+        &get_certbot_image(&acme),
+        ports,
+        volumes,
+        Some(envs),
+        Some(cmds),
+    )
+    .await
+}
+
+// returns the certbot image, dns plugins ship in their own images
+fn get_certbot_image(acme: &JinxAcme) -> String {
+    match &acme.dns_plugin {
+        Some(plugin) => format!("certbot/dns-{}", plugin),
+        None => "certbot/certbot".to_string(),
+    }
+}
+
+// returns the letsencrypt directories, and the dns plugin credentials when set
+fn get_certbot_volumes(jinx_files: &JinxFiles, acme: &JinxAcme) -> Vec<String> {
+    let mut volumes = vec![
+        format!("{}:/etc/letsencrypt", jinx_files.letsencrypt_conf),
+        format!("{}:/var/www/certbot", jinx_files.letsencrypt_www),
     ];
 
-    run_image(client, "certbot/certbot", ports, volumes, None, Some(cmds)).await
+    if let Some(credentials) = &acme.dns_credentials {
+        volumes.push(format!("{}:{}:ro", credentials, DNS_CREDENTIALS));
+    }
+
+    volumes
+}
+
+// returns the acme account and server arguments
+fn get_account_args(acme: &JinxAcme) -> Vec<String> {
+    let mut args = vec!["--non-interactive".to_string(), "--agree-tos".to_string()];
+
+    match &acme.email {
+        Some(email) => {
+            args.push("--email".to_string());
+            args.push(email.to_string());
+            args.push("--no-eff-email".to_string());
+        }
+        None => args.push("--register-unsafely-without-email".to_string()),
+    }
+
+    match &acme.server {
+        Some(server) => {
+            args.push("--server".to_string());
+            args.push(server.to_string());
+        }
+        None if acme.staging.unwrap_or(false) => args.push("--staging".to_string()),
+        None => {}
+    }
+
+    if acme.no_verify_ssl.unwrap_or(false) {
+        args.push("--no-verify-ssl".to_string());
+    }
+
+    args
+}
+
+// returns the dns-01 plugin arguments, or the http-01 arguments without a plugin
+fn get_challenge_args(acme: &JinxAcme, http_args: &[&str]) -> Vec<String> {
+    let plugin = match &acme.dns_plugin {
+        Some(plugin) => plugin,
+        None => return http_args.iter().map(|a| a.to_string()).collect(),
+    };
+
+    let mut args = vec![format!("--dns-{}", plugin)];
+
+    if acme.dns_credentials.is_some() {
+        args.push(format!("--dns-{}-credentials", plugin));
+        args.push(DNS_CREDENTIALS.to_string());
+    }
+
+    if let Some(seconds) = acme.dns_propagation_seconds {
+        args.push(format!("--dns-{}-propagation-seconds", plugin));
+        args.push(seconds.to_string());
+    }
+
+    args
+}
+
+// Struct that contains the expiry of a certificate as reported by certbot
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxCertExpiry {
+    pub cert_name: String,
+    pub domains: Vec<String>,
+    // e.g. "2024-03-01 12:00:00+00:00"
+    pub expiry_date: String,
+    // e.g. "VALID: 80 days" or "INVALID: EXPIRED"
+    pub status: String,
+}
+
+// Struct that contains a certificate found in the letsencrypt live directory
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxCertificate {
+    // name of the live/ directory
+    pub cert_name: String,
+    pub common_name: Option<String>,
+    // dns subject alternative names
+    pub sans: Vec<String>,
+    pub issuer: String,
+    // unix timestamp in seconds
+    pub not_after: i64,
+    // e.g. "Fri, 01 Mar 2024 12:00:00 +0000"
+    pub not_after_date: String,
+    pub expired: bool,
+    // set when fullchain.pem could not be read, leaving the other fields empty
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, std::cmp::PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JinxCertIssueKind {
+    // no certificate in live/<cert_name>
+    Missing,
+    Expired,
+    // fullchain.pem could not be read, with the reason
+    Unreadable(String),
+    // the certificate does not cover these server names
+    UncoveredDomains(Vec<String>),
+}
+
+// Struct that contains an https service without a usable certificate
+#[derive(Debug, Deserialize, Serialize, Clone, std::cmp::PartialEq)]
+pub struct JinxCertIssue {
+    pub service: String,
+    pub cert_name: String,
+    pub kind: JinxCertIssueKind,
+}
+
+// returns the certificates in the letsencrypt live directory
+pub fn get_certificates() -> Result<Vec<JinxCertificate>, JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    let live_dir = format!("{}/live", jinx_files.letsencrypt_conf);
+    if !Path::new(&live_dir).is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut certificates = vec![];
+    for entry in fs::read_dir(&live_dir)? {
+        let entry = entry?;
+        let fullchain = entry.path().join("fullchain.pem");
+        if !fullchain.is_file() {
+            continue;
+        }
+
+        let cert_name = entry.file_name().to_string_lossy().to_string();
+        let certificate = fs::read(&fullchain)
+            .map_err(JinxError::from)
+            .and_then(|bytes| parse_certificate(cert_name.to_string(), &bytes));
+
+        // one unreadable chain should not hide the others
+        certificates.push(match certificate {
+            Ok(certificate) => certificate,
+            Err(err) => JinxCertificate {
+                cert_name,
+                error: Some(err.to_string()),
+                ..Default::default()
+            },
+        });
+    }
+
+    certificates.sort_by(|a, b| a.cert_name.cmp(&b.cert_name));
+
+    Ok(certificates)
+}
+
+// returns the leaf certificate of a pem encoded chain
+fn parse_certificate(cert_name: String, bytes: &[u8]) -> Result<JinxCertificate, JinxError> {
+    let invalid = |err: String| {
+        JinxError::Certificate(format!("Failed to parse {}/fullchain.pem: {}", cert_name, err))
+    };
+
+    // the first certificate in the chain is the leaf
+    let pem = match Pem::iter_from_buffer(bytes).next() {
+        Some(pem) => pem.map_err(|err| invalid(err.to_string()))?,
+        None => return Err(invalid("no certificate found".to_string())),
+    };
+    let x509 = pem.parse_x509().map_err(|err| invalid(err.to_string()))?;
+
+    let common_name = x509
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .map(|cn| cn.to_string());
+
+    let mut sans = vec![];
+    if let Ok(Some(san)) = x509.subject_alternative_name() {
+        for name in san.value.general_names.iter() {
+            if let GeneralName::DNSName(dns_name) = name {
+                sans.push(dns_name.to_string());
+            }
+        }
+    }
+
+    let not_after = x509.validity().not_after;
+
+    Ok(JinxCertificate {
+        cert_name: cert_name.to_string(),
+        common_name,
+        sans,
+        issuer: x509.issuer().to_string(),
+        not_after: not_after.timestamp(),
+        not_after_date: not_after.to_rfc2822().unwrap_or_else(|_| not_after.to_string()),
+        expired: not_after < ASN1Time::now(),
+        error: None,
+    })
+}
+
+// returns true if the certificate name, which may be a wildcard, covers the domain
+fn covers_domain(name: &str, domain: &str) -> bool {
+    if name.eq_ignore_ascii_case(domain) {
+        return true;
+    }
+
+    // a wildcard covers exactly one label
+    match (name.strip_prefix("*."), domain.split_once('.')) {
+        (Some(parent), Some((_, domain_parent))) => parent.eq_ignore_ascii_case(domain_parent),
+        _ => false,
+    }
+}
+
+// returns the https services of the JinxConf whose certificate is missing, expired, or incomplete
+pub fn check_certificates(jinx_conf: &JinxConf) -> Result<Vec<JinxCertIssue>, JinxError> {
+    let certificates = get_certificates()?;
+
+    Ok(get_cert_issues(jinx_conf, &certificates))
+}
+
+// returns the issues of the JinxConf's https services against the certificates
+fn get_cert_issues(jinx_conf: &JinxConf, certificates: &[JinxCertificate]) -> Vec<JinxCertIssue> {
+    let mut issues = vec![];
+    // custom certificates are not managed by letsencrypt
+    for jinx_service in jinx_conf
+        .jinx_services
+        .iter()
+        .filter(|s| s.https && s.custom_cert.is_none())
+    {
+        let cert_name = jinx_service.get_cert_name();
+
+        let kind = match certificates.iter().find(|c| c.cert_name == cert_name) {
+            None => Some(JinxCertIssueKind::Missing),
+            Some(JinxCertificate {
+                error: Some(error), ..
+            }) => Some(JinxCertIssueKind::Unreadable(error.to_string())),
+            Some(certificate) if certificate.expired => Some(JinxCertIssueKind::Expired),
+            Some(certificate) => {
+                let uncovered: Vec<String> = jinx_service
+                    .get_server_names()
+                    .into_iter()
+                    .filter(|d| !certificate.sans.iter().any(|san| covers_domain(san, d)))
+                    .collect();
+
+                if uncovered.is_empty() {
+                    None
+                } else {
+                    Some(JinxCertIssueKind::UncoveredDomains(uncovered))
+                }
+            }
+        };
+
+        if let Some(kind) = kind {
+            issues.push(JinxCertIssue {
+                service: jinx_service.name.to_string(),
+                cert_name,
+                kind,
+            });
+        }
+    }
+
+    issues
+}
+
+// runs certbot with the letsencrypt directories mounted and returns its output
+async fn run_certbot(
+    client: Docker,
+    jinx_files: &JinxFiles,
+    acme: &JinxAcme,
+    cmds: Vec<&str>,
+) -> Result<String, JinxError> {
+    // mount volumes
+    let volumes = get_certbot_volumes(jinx_files, acme);
+    let volumes = volumes.iter().map(|v| v.as_str()).collect();
+    let envs = acme.dns_envs.clone().unwrap_or_default();
+    let envs = envs.iter().map(|e| e.as_str()).collect();
+
+    let (status_code, output) = run_image_to_completion(
+        client,
+        &get_certbot_image(acme),
+        volumes,
+        Some(envs),
+        Some(cmds.clone()),
+        vec![],
+    )
+    .await?;
+
+    if status_code != 0 {
+        return Err(JinxError::Command(format!(
+            "certbot {} failed: {}",
+            cmds.join(" "),
+            output.trim()
+        )));
+    }
+
+    Ok(output)
+}
+
+// renews certificates through the running proxy's acme-challenge location, then reloads nginx
+pub async fn renew_certificates(client: Docker) -> Result<Vec<JinxCertExpiry>, JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    // get acme settings
+    let acme = get_jinx_conf()?.acme.unwrap_or_default();
+
+    // webroot keeps ports 80 and 443 with the proxy, dns plugins need neither
+    let mut cmds = vec!["renew".to_string(), "--non-interactive".to_string()];
+    cmds.append(&mut get_challenge_args(
+        &acme,
+        &["--webroot", "-w", "/var/www/certbot"],
+    ));
+    let cmds = cmds.iter().map(|c| c.as_str()).collect();
+    let output = run_certbot(client.clone(), &jinx_files, &acme, cmds).await?;
+    print!("{}", output);
+
+    // pick up renewed certificates
+    reload_jinx_proxy(client.clone()).await?;
+
+    get_certificate_expiries(client).await
+}
+
+// returns the expiry of every certificate certbot manages
+pub async fn get_certificate_expiries(client: Docker) -> Result<Vec<JinxCertExpiry>, JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    // get acme settings
+    let acme = get_jinx_conf()?.acme.unwrap_or_default();
+
+    let output = run_certbot(client, &jinx_files, &acme, vec!["certificates"]).await?;
+
+    Ok(parse_certificates(&output))
+}
+
+// returns the certificates listed in certbot certificates output
+fn parse_certificates(output: &str) -> Vec<JinxCertExpiry> {
+    let mut expiries: Vec<JinxCertExpiry> = vec![];
+
+    for line in output.lines() {
+        let line = line.trim();
+
+        if let Some(cert_name) = line.strip_prefix("Certificate Name:") {
+            expiries.push(JinxCertExpiry {
+                cert_name: cert_name.trim().to_string(),
+                ..Default::default()
+            });
+            continue;
+        }
+
+        let expiry = match expiries.last_mut() {
+            Some(expiry) => expiry,
+            None => continue,
+        };
+
+        if let Some(domains) = line.strip_prefix("Domains:") {
+            expiry.domains = domains.split_whitespace().map(|d| d.to_string()).collect();
+        } else if let Some(date) = line.strip_prefix("Expiry Date:") {
+            // "2024-03-01 12:00:00+00:00 (VALID: 80 days)"
+            let date = date.trim();
+            match date.find('(') {
+                Some(i) => {
+                    expiry.expiry_date = date[..i].trim().to_string();
+                    expiry.status = date[i + 1..].trim_end_matches(')').trim().to_string();
+                }
+                None => expiry.expiry_date = date.to_string(),
+            }
+        }
+    }
+
+    expiries
 }
 
 // writes the paths for letsencrypt to mount with nginx
-pub fn write_letsencrypt() {
+pub fn write_letsencrypt() -> Result<(), JinxError> {
     // get jinx files
-    let jinx_files = get_jinx_files();
+    let jinx_files = get_jinx_files()?;
 
     // create conf dir
-    match fs::create_dir_all(jinx_files.letsencrypt_conf) {
-        Ok(dir) => dir,
-        Err(err) => log_exit!("[CERT] Failed to create letsencrypt conf directory", err),
-    };
+    fs::create_dir_all(jinx_files.letsencrypt_conf)?;
 
     // create www dir
-    match fs::create_dir_all(jinx_files.letsencrypt_www) {
-        Ok(dir) => dir,
-        Err(err) => log_exit!("[CERT] Failed to create letsencrypt www directory", err),
-    };
+    fs::create_dir_all(jinx_files.letsencrypt_www)?;
+
+    Ok(())
+}
+
+// returns a JinxError for a failed certificate generation
+fn generate_error(err: rcgen::Error) -> JinxError {
+    JinxError::Certificate(format!("Failed to generate certificate: {}", err))
+}
+
+// writes a private key readable only by its owner
+fn write_private_key(path: &str, key: &[u8]) -> Result<(), JinxError> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+
+    // mode only applies to new files, so tighten existing keys too
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(key)?;
+
+    Ok(())
+}
+
+// returns the jinx development CA and its pem, creating it in the certs dir on first use
+fn get_dev_ca() -> Result<(Certificate, KeyPair, String), JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    let ca_cert_path = format!("{}/ca.pem", jinx_files.jinx_certs);
+    let ca_key_path = format!("{}/ca-key.pem", jinx_files.jinx_certs);
+
+    // reuse the existing CA so browsers only need to trust it once
+    if Path::new(&ca_cert_path).is_file() && Path::new(&ca_key_path).is_file() {
+        let ca_pem = fs::read_to_string(&ca_cert_path)?;
+        // earlier versions wrote the key world readable
+        fs::set_permissions(&ca_key_path, fs::Permissions::from_mode(0o600))?;
+        let ca_key = KeyPair::from_pem(&fs::read_to_string(&ca_key_path)?).map_err(generate_error)?;
+
+        // the issuer only needs the CA's name and key to sign
+        let ca_params = CertificateParams::from_ca_cert_pem(&ca_pem).map_err(generate_error)?;
+        let ca_cert = ca_params.self_signed(&ca_key).map_err(generate_error)?;
+        return Ok((ca_cert, ca_key, ca_pem));
+    }
+
+    let mut ca_params = CertificateParams::default();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params
+        .distinguished_name
+        .push(DnType::CommonName, "jinx development CA");
+    ca_params.not_before = OffsetDateTime::now_utc();
+    ca_params.not_after = ca_params.not_before + Duration::days(10 * 365);
+
+    let ca_key = KeyPair::generate().map_err(generate_error)?;
+    let ca_cert = ca_params.self_signed(&ca_key).map_err(generate_error)?;
+
+    let ca_pem = ca_cert.pem();
+    fs::create_dir_all(&jinx_files.jinx_certs)?;
+    fs::write(&ca_cert_path, &ca_pem)?;
+    write_private_key(&ca_key_path, ca_key.serialize_pem().as_bytes())?;
+
+    Ok((ca_cert, ca_key, ca_pem))
+}
+
+// writes a certificate for every server name of the JinxService signed by the jinx development CA
+pub fn generate_dev_certificate(jinx_service: &JinxService) -> Result<JinxCustomCert, JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    let (ca_cert, ca_key, ca_pem) = get_dev_ca()?;
+
+    let mut params =
+        CertificateParams::new(jinx_service.get_server_names()).map_err(generate_error)?;
+    params
+        .distinguished_name
+        .push(DnType::CommonName, jinx_service.domain.as_str());
+    params.not_before = OffsetDateTime::now_utc();
+    params.not_after = params.not_before + Duration::days(DEV_CERT_DAYS);
+
+    let key = KeyPair::generate().map_err(generate_error)?;
+    let cert = params
+        .signed_by(&key, &ca_cert, &ca_key)
+        .map_err(generate_error)?;
+
+    // write the chain and key like letsencrypt's live directory
+    let cert_dir = format!("{}/{}", jinx_files.jinx_certs, jinx_service.get_cert_name());
+    fs::create_dir_all(&cert_dir)?;
+
+    let cert_path = format!("{}/fullchain.pem", cert_dir);
+    let key_path = format!("{}/privkey.pem", cert_dir);
+    fs::write(&cert_path, format!("{}{}", cert.pem(), ca_pem))?;
+    write_private_key(&key_path, key.serialize_pem().as_bytes())?;
+
+    Ok(JinxCustomCert {
+        cert_path: Some(cert_path),
+        key_path: Some(key_path),
+        cert_secret: None,
+        key_secret: None,
+    })
+}
+
+// writes a throwaway certificate so nginx -t can load certificates only the proxy can read
+pub fn write_placeholder_certificate(name: &str) -> Result<(String, String), JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    let params = CertificateParams::new(vec![name.to_string()]).map_err(generate_error)?;
+    let key = KeyPair::generate().map_err(generate_error)?;
+    let cert = params.self_signed(&key).map_err(generate_error)?;
+
+    let placeholder_dir = format!("{}/placeholder/{}", jinx_files.jinx_certs, name);
+    fs::create_dir_all(&placeholder_dir)?;
+
+    let cert_path = format!("{}/fullchain.pem", placeholder_dir);
+    let key_path = format!("{}/privkey.pem", placeholder_dir);
+    fs::write(&cert_path, cert.pem())?;
+    write_private_key(&key_path, key.serialize_pem().as_bytes())?;
+
+    Ok((cert_path, key_path))
+}
+
+// copies the custom certificates of https services from host files into the proxy_certs dir
+pub fn write_proxy_certificates(jinx_conf: &JinxConf) -> Result<(), JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    // the dir is mounted into the proxy, so it must exist even when empty
+    fs::create_dir_all(&jinx_files.proxy_certs)?;
+
+    for jinx_service in jinx_conf.jinx_services.iter().filter(|s| s.https) {
+        let (cert_path, key_path) = match &jinx_service.custom_cert {
+            Some(JinxCustomCert {
+                cert_path: Some(cert_path),
+                key_path: Some(key_path),
+                ..
+            }) => (cert_path, key_path),
+            _ => continue,
+        };
+
+        let cert = fs::read(cert_path)?;
+        let key = fs::read(key_path)?;
+
+        let cert_dir = format!("{}/{}", jinx_files.proxy_certs, jinx_service.name);
+        fs::create_dir_all(&cert_dir)?;
+        fs::write(format!("{}/fullchain.pem", cert_dir), cert)?;
+        write_private_key(&format!("{}/privkey.pem", cert_dir), &key)?;
+    }
+
+    Ok(())
+}
+
+// copies the client CA bundles of the JinxConf into the client_ca dir mounted by the proxy
+pub fn write_client_ca_files(jinx_conf: &JinxConf) -> Result<(), JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    // check every bundle before changing the dir
+    let mut bundles = vec![];
+    for jinx_service in jinx_conf.jinx_services.iter() {
+        jinx_service.get_client_ca_path()?;
+
+        let ca_path = match jinx_service.client_auth.as_ref().and_then(|c| c.ca_path.as_ref()) {
+            Some(ca_path) => ca_path,
+            None => continue,
+        };
+
+        let bytes = fs::read(ca_path)?;
+        let invalid = |err: String| {
+            JinxError::Certificate(format!("Failed to parse client CA {}: {}", ca_path, err))
+        };
+
+        let mut count = 0;
+        for pem in Pem::iter_from_buffer(&bytes) {
+            let pem = pem.map_err(|err| invalid(err.to_string()))?;
+            pem.parse_x509().map_err(|err| invalid(err.to_string()))?;
+            count += 1;
+        }
+        if count == 0 {
+            return Err(invalid("no certificate found".to_string()));
+        }
+
+        bundles.push((format!("{}.pem", jinx_service.name), bytes));
+    }
+
+    // the dir is mounted into the proxy, so it must exist even when empty
+    fs::create_dir_all(&jinx_files.client_ca_dir)?;
+
+    // bundles of removed services are kept, the loaded config may still use them until a reload
+    for (name, bytes) in bundles {
+        fs::write(format!("{}/{}", jinx_files.client_ca_dir, name), bytes)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // output of certbot certificates with a valid and an expired certificate
+    const CERTBOT_CERTIFICATES: &str = "Saving debug log to /var/log/letsencrypt/letsencrypt.log
+
+- - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+Found the following certs:
+  Certificate Name: www.example.com
+    Serial Number: 3f9a6c1e2b7d8e0f4a5b6c7d8e9f0a1b2c3
+    Key Type: ECDSA
+    Domains: example.com www.example.com
+    Expiry Date: 2024-03-01 12:00:00+00:00 (VALID: 80 days)
+    Certificate Path: /etc/letsencrypt/live/www.example.com/fullchain.pem
+    Private Key Path: /etc/letsencrypt/live/www.example.com/privkey.pem
+  Certificate Name: api.example.com
+    Serial Number: 4a0b1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6
+    Key Type: RSA
+    Domains: api.example.com
+    Expiry Date: 2023-11-20 08:30:00+00:00 (INVALID: EXPIRED)
+    Certificate Path: /etc/letsencrypt/live/api.example.com/fullchain.pem
+    Private Key Path: /etc/letsencrypt/live/api.example.com/privkey.pem
+- - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+";
+
+    #[test]
+    fn parses_certbot_certificates() {
+        let expiries = parse_certificates(CERTBOT_CERTIFICATES);
+
+        assert_eq!(
+            expiries,
+            vec![
+                JinxCertExpiry {
+                    cert_name: "www.example.com".to_string(),
+                    domains: vec!["example.com".to_string(), "www.example.com".to_string()],
+                    expiry_date: "2024-03-01 12:00:00+00:00".to_string(),
+                    status: "VALID: 80 days".to_string(),
+                },
+                JinxCertExpiry {
+                    cert_name: "api.example.com".to_string(),
+                    domains: vec!["api.example.com".to_string()],
+                    expiry_date: "2023-11-20 08:30:00+00:00".to_string(),
+                    status: "INVALID: EXPIRED".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_certbot_without_certificates() {
+        let output = "Saving debug log to /var/log/letsencrypt/letsencrypt.log
+
+- - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+No certificates found.
+- - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+";
+
+        assert!(parse_certificates(output).is_empty());
+    }
+
+    #[test]
+    fn parses_expiry_without_status() {
+        let output = "  Certificate Name: example.com
+    Expiry Date: 2024-03-01 12:00:00+00:00
+";
+        let expiries = parse_certificates(output);
+
+        assert_eq!(expiries.len(), 1);
+        assert_eq!(expiries[0].expiry_date, "2024-03-01 12:00:00+00:00");
+        assert!(expiries[0].status.is_empty());
+        assert!(expiries[0].domains.is_empty());
+    }
+
+    // returns the pem of a self-signed certificate for the names, expiring in days
+    fn get_test_pem(names: &[&str], days: i64) -> String {
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        let mut params = CertificateParams::new(names).unwrap();
+        params.not_after = OffsetDateTime::now_utc() + Duration::days(days);
+        params.not_before = params.not_after - Duration::days(90);
+        let key = KeyPair::generate().unwrap();
+
+        params.self_signed(&key).unwrap().pem()
+    }
+
+    // returns an https service of the domain
+    fn get_test_service(name: &str, domain: &str) -> JinxService {
+        JinxService {
+            name: name.to_string(),
+            domain: domain.to_string(),
+            https: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_certificate_chains() {
+        let pem = get_test_pem(&["example.com", "www.example.com"], 30);
+        let chain = format!("{}{}", pem, get_test_pem(&["issuer.example.com"], 365));
+
+        let certificate =
+            parse_certificate("www.example.com".to_string(), chain.as_bytes()).unwrap();
+        assert_eq!(certificate.cert_name, "www.example.com");
+        assert_eq!(certificate.sans, vec!["example.com", "www.example.com"]);
+        assert!(!certificate.expired);
+        assert_eq!(certificate.error, None);
+
+        let certificate =
+            parse_certificate("old".to_string(), get_test_pem(&["old.com"], -1).as_bytes())
+                .unwrap();
+        assert!(certificate.expired);
+
+        for bytes in [
+            "",
+            "not a certificate",
+            "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n",
+        ] {
+            assert!(parse_certificate("bad".to_string(), bytes.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn covers_wildcard_domains() {
+        assert!(covers_domain("example.com", "EXAMPLE.com"));
+        assert!(covers_domain("*.example.com", "api.example.com"));
+        assert!(!covers_domain("*.example.com", "example.com"));
+        assert!(!covers_domain("*.example.com", "a.api.example.com"));
+        assert!(!covers_domain("example.com", "www.example.com"));
+    }
+
+    #[test]
+    fn reports_certificate_issues() {
+        let certificate = |cert_name: &str, names: &[&str], days: i64| {
+            parse_certificate(cert_name.to_string(), get_test_pem(names, days).as_bytes()).unwrap()
+        };
+        let certificates = vec![
+            certificate("www.valid.com", &["valid.com", "www.valid.com"], 30),
+            certificate("www.expired.com", &["expired.com", "www.expired.com"], -1),
+            certificate("_wildcard.wild.com", &["*.wild.com"], 30),
+            certificate("www.partial.com", &["partial.com"], 30),
+            JinxCertificate {
+                cert_name: "www.broken.com".to_string(),
+                error: Some("no certificate found".to_string()),
+                ..Default::default()
+            },
+        ];
+
+        let mut custom = get_test_service("custom", "custom.com");
+        custom.custom_cert = Some(JinxCustomCert::default());
+        let jinx_conf = JinxConf {
+            jinx_services: vec![
+                get_test_service("valid", "valid.com"),
+                get_test_service("expired", "expired.com"),
+                get_test_service("wild", "*.wild.com"),
+                get_test_service("partial", "partial.com"),
+                get_test_service("broken", "broken.com"),
+                get_test_service("missing", "missing.com"),
+                // not managed by letsencrypt
+                custom,
+                JinxService {
+                    https: false,
+                    ..get_test_service("plain", "plain.com")
+                },
+            ],
+            ..Default::default()
+        };
+
+        let issues: Vec<(String, JinxCertIssueKind)> = get_cert_issues(&jinx_conf, &certificates)
+            .into_iter()
+            .map(|i| (i.service, i.kind))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                ("expired".to_string(), JinxCertIssueKind::Expired),
+                (
+                    "partial".to_string(),
+                    JinxCertIssueKind::UncoveredDomains(vec!["www.partial.com".to_string()])
+                ),
+                (
+                    "broken".to_string(),
+                    JinxCertIssueKind::Unreadable("no certificate found".to_string())
+                ),
+                ("missing".to_string(), JinxCertIssueKind::Missing),
+            ]
+        );
+    }
 }
@@ -5,9 +5,50 @@ use std::fs::File;
 use std::io::BufReader;
 use std::io::ErrorKind;
 
-use super::log_exit;
+use crate::error::JinxError;
 use crate::file::{get_jinx_files, JinxFiles};
-use crate::service::JinxService;
+use crate::service::{JinxPlacement, JinxService};
+
+// mozilla server side tls configurations
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, std::cmp::PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JinxTlsProfile {
+  // TLSv1.3 only
+  Modern,
+  // TLSv1.2 and TLSv1.3, the default
+  Intermediate,
+  // TLSv1 and up for very old clients
+  Legacy,
+}
+
+// Struct that contains the acme account, server, and challenge used to request certificates
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct JinxAcme {
+  // defaults to registering without a contact email
+  pub email: Option<String>,
+  // uses the letsencrypt staging server, for testing
+  pub staging: Option<bool>,
+  // acme directory url, e.g. a local pebble, overrides staging
+  pub server: Option<String>,
+  // skips verifying the acme server's certificate, for pebble's self-signed one
+  pub no_verify_ssl: Option<bool>,
+  // certbot dns plugin such as "cloudflare" or "route53", defaults to the http-01 challenge
+  pub dns_plugin: Option<String>,
+  // host path of the plugin's credentials ini
+  pub dns_credentials: Option<String>,
+  // plugin environment such as AWS_ACCESS_KEY_ID=...
+  pub dns_envs: Option<Vec<String>>,
+  pub dns_propagation_seconds: Option<u32>,
+}
+
+// Struct that contains a docker secret holding the generated htpasswd file of a user group
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct JinxHtpasswdSecret {
+  // user group of jinx_htpasswd.json
+  pub group: String,
+  // name:id, like image_secrets
+  pub secret: String,
+}
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct JinxConf {
@@ -15,6 +56,13 @@ pub struct JinxConf {
   pub nginx_worker_processes: u8,
   pub nginx_worker_connections: u16,
   pub jinx_services: Vec<JinxService>,
+  pub jinx_proxy_placement: Option<JinxPlacement>,
+  pub tls_profile: Option<JinxTlsProfile>,
+  // defaults to false, tickets weaken forward secrecy unless keys are rotated
+  pub ssl_session_tickets: Option<bool>,
+  pub acme: Option<JinxAcme>,
+  // htpasswd groups without a secret are mounted from the htpasswd dir
+  pub htpasswd_secrets: Option<Vec<JinxHtpasswdSecret>>,
 }
 
 impl Default for JinxConf {
@@ -24,79 +72,73 @@ impl Default for JinxConf {
       nginx_worker_processes: 1,
       nginx_worker_connections: 1024,
       jinx_services: vec![],
+      jinx_proxy_placement: None,
+      tls_profile: None,
+      ssl_session_tickets: None,
+      acme: None,
+      htpasswd_secrets: None,
     }
   }
 }
 
-pub fn open_jinx_conf(jinx_files: &JinxFiles) -> File {
+pub fn open_jinx_conf(jinx_files: &JinxFiles) -> Result<File, JinxError> {
   // try to open jinx_conf
-  let jinx_conf_file = match File::open(&jinx_files.jinx_conf) {
+  match File::open(&jinx_files.jinx_conf) {
     Err(err) => {
       // create default jinx_conf if not found
-      if err.kind() == ErrorKind::NotFound {
-        // create jinx directory
-        let _dir = match fs::create_dir_all(&jinx_files.jinx_home) {
-          Err(err) => log_exit!("[JINX] Failed to create jinx directory", err),
-          Ok(dir) => dir,
-        };
-
-        // create default jinx_conf
-        let jinx_conf = JinxConf {
-          ..Default::default()
-        };
-        let json = json!(jinx_conf);
-
-        // write file
-        fs::write(&jinx_files.jinx_conf, &json.to_string().as_bytes())
-          .expect("[CONF] Failed to write jinx_conf");
-
-        // return file
-        match File::open(&jinx_files.jinx_conf) {
-          Err(err) => log_exit!("[CONF] Failed to open jinx_conf", err),
-          Ok(file) => file,
-        }
-      } else {
-        log_exit!("[CONF] Failed to open jinx_conf", err)
+      if err.kind() != ErrorKind::NotFound {
+        return Err(err.into());
       }
-    }
-    Ok(file) => file,
-  };
 
-  jinx_conf_file
+      // create jinx directory
+      fs::create_dir_all(&jinx_files.jinx_home)?;
+
+      // create default jinx_conf
+      let jinx_conf = JinxConf {
+        ..Default::default()
+      };
+      let json = json!(jinx_conf);
+
+      // write file
+      fs::write(&jinx_files.jinx_conf, json.to_string().as_bytes())?;
+
+      // return file
+      Ok(File::open(&jinx_files.jinx_conf)?)
+    }
+    Ok(file) => Ok(file),
+  }
 }
 
 // returns JinxConf
-pub fn get_jinx_conf() -> JinxConf {
+pub fn get_jinx_conf() -> Result<JinxConf, JinxError> {
   // get jinx files
-  let jinx_files = get_jinx_files();
+  let jinx_files = get_jinx_files()?;
 
   // open jinx conf
-  let jinx_conf_file = open_jinx_conf(&jinx_files);
+  let jinx_conf_file = open_jinx_conf(&jinx_files)?;
 
   // read the file
   let reader = BufReader::new(jinx_conf_file);
 
   // parse jinx_conf.json into a JinxConf
-  let jinx_conf: JinxConf = match serde_json::from_reader(reader) {
-    Err(err) => log_exit!("[CONF] Failed to parse jinx_conf", err),
-    Ok(file) => file,
-  };
+  let jinx_conf: JinxConf = serde_json::from_reader(reader)?;
 
-  jinx_conf
+  Ok(jinx_conf)
 }
 
 // writes JinxConf to jinx_conf file
-pub fn write_jinx_conf(jinx_conf: &JinxConf) {
+pub fn write_jinx_conf(jinx_conf: &JinxConf) -> Result<(), JinxError> {
   // get jinx files
-  let jinx_files = get_jinx_files();
+  let jinx_files = get_jinx_files()?;
 
   // ensure path exists
-  let _jinx_conf_file = open_jinx_conf(&jinx_files);
+  open_jinx_conf(&jinx_files)?;
 
   // convert jinx_conf to JSON
   let json = json!(jinx_conf);
 
   // write the file
-  fs::write(&jinx_files.jinx_conf, &json.to_string().as_bytes())
-    .expect("[CONF] Failed to write jinx_conf");
+  fs::write(&jinx_files.jinx_conf, json.to_string().as_bytes())?;
+
+  Ok(())
 }
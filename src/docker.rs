@@ -1,25 +1,43 @@
-use bollard::container::{Config, CreateContainerOptions};
-use bollard::image::BuildImageOptions;
-use bollard::models::{HostConfig, PortBinding};
+use bollard::container::{
+    Config, CreateContainerOptions, ListContainersOptions, LogsOptions, RemoveContainerOptions,
+};
+use bollard::exec::{CreateExecOptions, StartExecResults};
+use bollard::image::{BuildImageOptions, CreateImageOptions};
+use bollard::models::{HealthConfig, HostConfig, Limit, PortBinding, ResourceObject};
 use bollard::network::CreateNetworkOptions;
 use bollard::service::{
-    EndpointPortConfig, EndpointSpec, Mount, MountTypeEnum, NetworkAttachmentConfig, ServiceSpec,
-    ServiceSpecMode, ServiceSpecModeReplicated, TaskSpec, TaskSpecContainerSpec,
-    TaskSpecContainerSpecFile, TaskSpecContainerSpecSecrets,
+    EndpointPortConfig, EndpointPortConfigPublishModeEnum, EndpointSpec, EndpointSpecModeEnum,
+    Mount, MountTypeEnum, NetworkAttachmentConfig, ServiceSpec, ServiceSpecMode,
+    ServiceSpecModeReplicated, ServiceSpecRollbackConfig,
+    ServiceSpecRollbackConfigFailureActionEnum, ServiceSpecRollbackConfigOrderEnum,
+    ServiceSpecUpdateConfig, ServiceSpecUpdateConfigFailureActionEnum,
+    ServiceSpecUpdateConfigOrderEnum, TaskSpec, TaskSpecContainerSpec, TaskSpecContainerSpecFile,
+    TaskSpecContainerSpecSecrets, TaskSpecPlacement, TaskSpecPlacementPreferences,
+    TaskSpecPlacementSpread, TaskSpecResources, UpdateServiceOptions,
 };
 use bollard::Docker;
 use futures_util::stream::StreamExt;
+use serde_derive::Deserialize;
+use serde_json::json;
 use std::collections::HashMap;
 use std::env;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
 
-use super::log_exit;
-use crate::service::JinxService;
+use crate::error::JinxError;
+use crate::service::{
+    JinxEndpointMode, JinxFailureAction, JinxHealthcheck, JinxPlacement, JinxResources,
+    JinxService, JinxServiceMode, JinxServiceResources, JinxUpdateConfig, JinxUpdateOrder,
+};
+use crate::units::{parse_cpus, parse_duration, parse_memory};
 
 // builds the provided tar.gz file with meta from the JinxService
-pub async fn build_docker_image(client: Docker, jinx_service: &JinxService, bytes: Vec<u8>) {
+pub async fn build_docker_image(
+    client: Docker,
+    jinx_service: &JinxService,
+    bytes: Vec<u8>,
+) -> Result<(), JinxError> {
     // define image options
     let config = BuildImageOptions {
         dockerfile: "Dockerfile",
@@ -30,10 +48,17 @@ pub async fn build_docker_image(client: Docker, jinx_service: &JinxService, byte
     let mut image_build_stream = client.build_image(config, None, Some(bytes.into()));
 
     while let Some(msg) = image_build_stream.next().await {
-        let message = match msg {
-            Ok(msg) => msg,
-            Err(err) => log_exit!("[DOCKER] Failed to get build message", err),
-        };
+        let message = msg?;
+
+        // failed steps are reported in the stream rather than as an api error
+        if let Some(error) = message.error {
+            return Err(JinxError::Image(format!(
+                "Failed to build {}: {}",
+                jinx_service.image_name,
+                error.trim()
+            )));
+        }
+
         let stream = match message.stream {
             Some(stream) => stream,
             None => "".to_string(),
@@ -41,10 +66,12 @@ pub async fn build_docker_image(client: Docker, jinx_service: &JinxService, byte
 
         print!("{}", stream);
     }
+
+    Ok(())
 }
 
 // creates a docker network
-pub async fn create_jinx_network(client: Docker) {
+pub async fn create_jinx_network(client: Docker) -> Result<(), JinxError> {
     // define jinx network
     let config = CreateNetworkOptions {
         name: "jinx_network",
@@ -54,35 +81,31 @@ pub async fn create_jinx_network(client: Docker) {
         ..Default::default()
     };
 
-    let network_id = match client.create_network(config).await {
-        Ok(id) => id,
-        Err(err) => log_exit!("[DOCKER] Failed to create jinx_network", err),
-    };
+    let network_id = client.create_network(config).await?;
 
     println!("Jinx network created: {:?}", network_id);
+
+    Ok(())
 }
 
 // returns a Docker client
-pub fn get_client() -> Docker {
-    let docker = match Docker::connect_with_socket_defaults() {
-        Ok(docker) => docker,
-        Err(err) => log_exit!("[DOCKER] Failed to connect to docker socket", err),
-    };
+pub fn get_client() -> Result<Docker, JinxError> {
+    let docker = Docker::connect_with_socket_defaults()?;
 
-    docker
+    Ok(docker)
 }
 
 // returns a vector of lines from the .dockerignore file
-pub fn get_dockerignore() -> Vec<String> {
+pub fn get_dockerignore() -> Result<Vec<String>, JinxError> {
     let mut lines = vec![];
 
     // get current directory
-    let current_dir = env::current_dir().expect("[DOCKER] Failed to get current directory");
+    let current_dir = env::current_dir()?;
 
     // attempt to open .dockerignore in current directory
     let jinx_path = format!("{}/.dockerignore", current_dir.display());
     let file = match File::open(jinx_path) {
-        Err(_err) => return lines,
+        Err(_err) => return Ok(lines),
         Ok(file) => file,
     };
 
@@ -91,30 +114,241 @@ pub fn get_dockerignore() -> Vec<String> {
 
     // add lines to array
     for line in reader.lines() {
-        let ln = match line {
-            Err(err) => format!("Error: {}", err),
-            Ok(line) => line,
-        };
-        lines.push(ln);
+        lines.push(line?);
     }
 
-    lines
+    Ok(lines)
 }
 
-// creates a docker service
-pub async fn create_service(client: Docker, jinx_service: &JinxService) {
+// creates a docker service, updating it when it already exists
+pub async fn create_service(client: Docker, jinx_service: &JinxService) -> Result<(), JinxError> {
     // create service name with jinx tag
-    let name = format!("{}{}", &jinx_service.name, "-jinx".to_string());
+    let name = format!("{}-jinx", &jinx_service.name);
 
-    _create_service(client, jinx_service, name).await;
+    _create_service(client, jinx_service, name).await
 }
 
-// creates a jinx proxy service
-pub async fn create_jinx_proxy_service(client: Docker, jinx_service: &JinxService) {
+// creates a jinx proxy service, updating it when it already exists
+pub async fn create_jinx_proxy_service(
+    client: Docker,
+    jinx_service: &JinxService,
+) -> Result<(), JinxError> {
     // create jinx proxy service
     let name = "jinx-proxy".to_string();
 
-    _create_service(client, jinx_service, name).await;
+    _create_service(client, jinx_service, name).await
+}
+
+// scales a replicated docker service to the provided number of replicas
+pub async fn scale_service(
+    client: Docker,
+    jinx_service: &JinxService,
+    replicas: i64,
+) -> Result<(), JinxError> {
+    // create service name with jinx tag
+    let name = format!("{}-jinx", &jinx_service.name);
+
+    if replicas < 0 {
+        return Err(JinxError::InvalidConfig(format!(
+            "replicas must not be negative, got {}",
+            replicas
+        )));
+    }
+
+    // get the current spec and version of the service
+    let service = client.inspect_service(&name, None).await?;
+    let version = service.version.and_then(|v| v.index).unwrap_or(0);
+    let mut spec = service.spec.unwrap_or_default();
+
+    // global services run on every node and cannot be scaled
+    let is_global = spec.mode.as_ref().is_some_and(|m| m.global.is_some());
+    if is_global {
+        return Err(JinxError::InvalidConfig(format!(
+            "{} is a global service and cannot be scaled",
+            name
+        )));
+    }
+
+    spec.mode = Some(ServiceSpecMode {
+        replicated: Some(ServiceSpecModeReplicated {
+            replicas: Some(replicas),
+        }),
+        ..Default::default()
+    });
+
+    let options = UpdateServiceOptions {
+        version,
+        ..Default::default()
+    };
+
+    client.update_service(&name, spec, options, None).await?;
+
+    println!("Jinx service scaled: {} to {} replicas", name, replicas);
+
+    Ok(())
+}
+
+// rolls a docker service back to its previous spec
+pub async fn rollback_service(client: Docker, jinx_service: &JinxService) -> Result<(), JinxError> {
+    // create service name with jinx tag
+    let name = format!("{}-jinx", &jinx_service.name);
+
+    // get the current spec and version of the service
+    let service = client.inspect_service(&name, None).await?;
+    let version = service.version.and_then(|v| v.index).unwrap_or(0);
+    let spec = service.spec.unwrap_or_default();
+
+    // swarm ignores the provided spec and restores the previous one
+    let options = UpdateServiceOptions {
+        version,
+        rollback: true,
+        ..Default::default()
+    };
+
+    client.update_service(&name, spec, options, None).await?;
+
+    println!("Jinx service rolled back: {}", name);
+
+    Ok(())
+}
+
+// path of the docker socket get_client connects to
+const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+// a swarm task, only the fields jinx reads
+#[derive(Debug, Deserialize)]
+struct SwarmTask {
+    #[serde(rename = "NodeID")]
+    node_id: Option<String>,
+}
+
+// returns the tasks swarm keeps running for a service, bollard has no tasks api
+async fn list_service_tasks(service_name: &str) -> Result<Vec<SwarmTask>, JinxError> {
+    let failed = |err: String| {
+        JinxError::Command(format!("Failed to list tasks of {}: {}", service_name, err))
+    };
+
+    // percent encode the json filters
+    let filters = json!({ "service": [service_name], "desired-state": ["running"] }).to_string();
+    let mut query = String::new();
+    for byte in filters.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            query.push(byte as char);
+        } else {
+            query.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    let uri = hyperlocal::Uri::new(DOCKER_SOCKET, &format!("/tasks?filters={}", query));
+    let client = hyper::Client::builder().build::<_, hyper::Body>(hyperlocal::UnixConnector);
+    let response = client
+        .get(uri.into())
+        .await
+        .map_err(|err| failed(err.to_string()))?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|err| failed(err.to_string()))?;
+
+    if !status.is_success() {
+        return Err(failed(String::from_utf8_lossy(&body).trim().to_string()));
+    }
+
+    Ok(serde_json::from_slice(&body)?)
+}
+
+// runs a command in every container of a swarm service, returning each container's output
+pub async fn exec_in_service(
+    client: Docker,
+    service_name: &str,
+    cmds: Vec<&str>,
+) -> Result<Vec<String>, JinxError> {
+    // find running containers of the service on this node
+    let mut filters = HashMap::new();
+    filters.insert(
+        "label".to_string(),
+        vec![format!("com.docker.swarm.service.name={}", service_name)],
+    );
+    filters.insert("status".to_string(), vec!["running".to_string()]);
+    let options = ListContainersOptions {
+        filters,
+        ..Default::default()
+    };
+    let containers = client.list_containers(Some(options)).await?;
+
+    if containers.is_empty() {
+        return Err(JinxError::Command(format!(
+            "No running containers found for {}",
+            service_name
+        )));
+    }
+
+    // the daemon only reaches its own containers, so tasks on other nodes would be skipped
+    let info = client.info().await?;
+    let node_id = info.swarm.and_then(|s| s.node_id).unwrap_or_default();
+    let tasks = list_service_tasks(service_name).await?;
+    let remote_tasks = tasks
+        .iter()
+        .filter(|t| t.node_id.as_deref() != Some(node_id.as_str()))
+        .count();
+    if remote_tasks > 0 {
+        return Err(JinxError::Command(format!(
+            "{} runs {} of its {} tasks on other nodes, run jinx on the nodes running them",
+            service_name,
+            remote_tasks,
+            tasks.len()
+        )));
+    }
+
+    let mut outputs = vec![];
+    for container in containers.iter() {
+        let container_id = match &container.id {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let config = CreateExecOptions {
+            cmd: Some(cmds.clone()),
+            attach_stdout: Some(true),
+            attach_stderr: Some(true),
+            ..Default::default()
+        };
+        let exec = client.create_exec(container_id, config).await?;
+
+        // collect the command output
+        let mut output = String::new();
+        if let StartExecResults::Attached { output: mut stream, .. } =
+            client.start_exec(&exec.id, None).await?
+        {
+            while let Some(msg) = stream.next().await {
+                output.push_str(&msg?.to_string());
+            }
+        }
+
+        // fail when the command exited with an error
+        let inspect = client.inspect_exec(&exec.id).await?;
+        if inspect.exit_code.unwrap_or(0) != 0 {
+            return Err(JinxError::Command(format!(
+                "{} failed in {}: {}",
+                cmds.join(" "),
+                container_id,
+                output.trim()
+            )));
+        }
+
+        outputs.push(output);
+    }
+
+    Ok(outputs)
+}
+
+// reloads nginx in the running jinx proxy containers without dropping connections
+pub async fn reload_jinx_proxy(client: Docker) -> Result<(), JinxError> {
+    exec_in_service(client, "jinx-proxy", vec!["nginx", "-s", "reload"]).await?;
+
+    println!("Jinx proxy reloaded");
+
+    Ok(())
 }
 
 // runs an image
@@ -125,17 +359,160 @@ pub async fn run_image(
     vols: Vec<&str>,
     envs: Option<Vec<&str>>,
     cmds: Option<Vec<&str>>,
-) {
-    let name = image_name.replace("/", "_");
+) -> Result<(), JinxError> {
+    let name = image_name.replace('/', "_");
     let options = Some(CreateContainerOptions {
         name: format!("jinx-{}", name),
     });
 
-    let mut volumes: HashMap<&str, HashMap<(), ()>> = HashMap::new();
+    let config = get_container_config(image_name, ports, vols, envs, cmds)?;
+
+    pull_image(&client, image_name).await?;
 
+    let container_id = client.create_container(options, config).await?;
+
+    println!("Created container: {:?}", container_id.id);
+
+    client
+        .start_container::<String>(&container_id.id, None)
+        .await?;
+
+    println!("Started container: {:?}", container_id.id);
+
+    Ok(())
+}
+
+// runs an image until it exits, removes the container, and returns its exit code and output
+pub async fn run_image_to_completion(
+    client: Docker,
+    image_name: &str,
+    vols: Vec<&str>,
+    envs: Option<Vec<&str>>,
+    cmds: Option<Vec<&str>>,
+    extra_hosts: Vec<String>,
+) -> Result<(i64, String), JinxError> {
+    let mut config = get_container_config(image_name, vec![], vols, envs, cmds)?;
+    if let Some(host_config) = config.host_config.as_mut() {
+        host_config.extra_hosts = Some(extra_hosts);
+    }
+
+    pull_image(&client, image_name).await?;
+
+    let container_id = client
+        .create_container::<String, &str>(None, config)
+        .await?
+        .id;
+
+    let result = _wait_for_container(&client, &container_id).await;
+
+    // remove the container even when waiting failed
+    let options = RemoveContainerOptions {
+        force: true,
+        ..Default::default()
+    };
+    client
+        .remove_container(&container_id, Some(options))
+        .await?;
+
+    result
+}
+
+// starts a created container and returns its exit code and output once it exits
+async fn _wait_for_container(
+    client: &Docker,
+    container_id: &str,
+) -> Result<(i64, String), JinxError> {
+    client
+        .start_container::<String>(container_id, None)
+        .await?;
+
+    let mut status_code = 0;
+    let mut wait_stream = client.wait_container::<String>(container_id, None);
+    while let Some(msg) = wait_stream.next().await {
+        status_code = msg?.status_code;
+    }
+
+    let options = LogsOptions::<String> {
+        stdout: true,
+        stderr: true,
+        ..Default::default()
+    };
+    let mut output = String::new();
+    let mut logs_stream = client.logs(container_id, Some(options));
+    while let Some(msg) = logs_stream.next().await {
+        output.push_str(&msg?.to_string());
+    }
+
+    Ok((status_code, output))
+}
+
+// returns the repository and tag of an image, defaulting the tag to latest
+fn get_image_tag(image_name: &str) -> Result<(&str, &str), JinxError> {
+    // a colon before the last slash belongs to a registry port
+    let name_start = image_name.rfind('/').map(|i| i + 1).unwrap_or(0);
+    let (repository, tag) = match image_name.find('@') {
+        Some(i) => (&image_name[..i], &image_name[i + 1..]),
+        None => match image_name[name_start..].rfind(':') {
+            Some(i) => (
+                &image_name[..name_start + i],
+                &image_name[name_start + i + 1..],
+            ),
+            None => (image_name, "latest"),
+        },
+    };
+
+    if repository.is_empty() || tag.is_empty() {
+        return Err(JinxError::InvalidConfig(format!(
+            "image {} needs a repository and a tag or digest",
+            image_name
+        )));
+    }
+
+    Ok((repository, tag))
+}
+
+// pulls an image from its registry
+async fn pull_image(client: &Docker, image_name: &str) -> Result<(), JinxError> {
+    // an empty tag pulls every tag of the repository
+    let (repository, tag) = get_image_tag(image_name)?;
+    let options = CreateImageOptions {
+        from_image: repository,
+        tag,
+        ..Default::default()
+    };
+
+    let mut pull_stream = client.create_image(Some(options), None, None);
+    while let Some(msg) = pull_stream.next().await {
+        // failed pulls are reported in the stream rather than as an api error
+        if let Some(error) = msg?.error {
+            return Err(JinxError::Image(format!(
+                "Failed to pull {}: {}",
+                image_name,
+                error.trim()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+// returns the container config binding the host:container ports and volumes
+fn get_container_config<'a>(
+    image_name: &'a str,
+    ports: Vec<&str>,
+    vols: Vec<&str>,
+    envs: Option<Vec<&'a str>>,
+    cmds: Option<Vec<&'a str>>,
+) -> Result<Config<&'a str>, JinxError> {
     let mut port_bindings = HashMap::new();
     for port in ports {
         let split: Vec<&str> = port.split(':').collect();
+        if split.len() < 2 {
+            return Err(JinxError::InvalidConfig(format!(
+                "port must be host:container, got {}",
+                port
+            )));
+        }
         let p = vec![PortBinding {
             host_ip: None,
             host_port: Some(split[0].to_string()),
@@ -144,43 +521,298 @@ pub async fn run_image(
         port_bindings.insert(split[1].to_string(), Some(p));
     }
 
+    let mut binds = vec![];
+    for vol in vols {
+        if !vol.contains(':') {
+            return Err(JinxError::InvalidConfig(format!(
+                "volume must be host:container, got {}",
+                vol
+            )));
+        }
+        binds.push(vol.to_string());
+    }
+
     let host_config = HostConfig {
         port_bindings: Some(port_bindings),
+        binds: Some(binds),
         ..Default::default()
     };
 
-    for vol in vols {
-        volumes.insert(vol, HashMap::new());
-    }
-
-    let config = Config {
+    Ok(Config {
         image: Some(image_name),
-        volumes: Some(volumes),
         cmd: cmds,
         env: envs,
         host_config: Some(host_config),
         ..Default::default()
-    };
+    })
+}
+
+// returns the swarm mode of the JinxService, defaulting to a single replica
+fn get_service_mode(jinx_service: &JinxService) -> Result<ServiceSpecMode, JinxError> {
+    let mode = jinx_service.mode.unwrap_or(JinxServiceMode::Replicated);
+
+    match mode {
+        JinxServiceMode::Replicated => {
+            let replicas = jinx_service.replicas.unwrap_or(1);
+            if replicas < 0 {
+                return Err(JinxError::InvalidConfig(format!(
+                    "replicas must not be negative, got {}",
+                    replicas
+                )));
+            }
+
+            Ok(ServiceSpecMode {
+                replicated: Some(ServiceSpecModeReplicated {
+                    replicas: Some(replicas),
+                }),
+                ..Default::default()
+            })
+        }
+        JinxServiceMode::Global => {
+            if jinx_service.replicas.is_some() {
+                return Err(JinxError::InvalidConfig(format!(
+                    "{} is a global service and cannot set replicas",
+                    jinx_service.name
+                )));
+            }
+
+            Ok(ServiceSpecMode {
+                global: Some(HashMap::new()),
+                ..Default::default()
+            })
+        }
+    }
+}
+
+// returns the swarm update config of the JinxUpdateConfig
+fn get_update_config(config: &JinxUpdateConfig) -> Result<ServiceSpecUpdateConfig, JinxError> {
+    let failure_action = config.failure_action.map(|action| match action {
+        JinxFailureAction::Continue => ServiceSpecUpdateConfigFailureActionEnum::CONTINUE,
+        JinxFailureAction::Pause => ServiceSpecUpdateConfigFailureActionEnum::PAUSE,
+        JinxFailureAction::Rollback => ServiceSpecUpdateConfigFailureActionEnum::ROLLBACK,
+    });
+    let order = config.order.map(|order| match order {
+        JinxUpdateOrder::StopFirst => ServiceSpecUpdateConfigOrderEnum::STOP_FIRST,
+        JinxUpdateOrder::StartFirst => ServiceSpecUpdateConfigOrderEnum::START_FIRST,
+    });
 
-    let container_id = match client.create_container(options, config).await {
-        Ok(id) => id,
-        Err(err) => log_exit!("[DOCKER] Failed to create image", err),
+    Ok(ServiceSpecUpdateConfig {
+        parallelism: config.parallelism,
+        delay: config.delay.as_deref().map(parse_duration).transpose()?,
+        failure_action,
+        monitor: config.monitor.as_deref().map(parse_duration).transpose()?,
+        max_failure_ratio: config.max_failure_ratio,
+        order,
+    })
+}
+
+// returns the swarm rollback config of the JinxUpdateConfig
+fn get_rollback_config(config: &JinxUpdateConfig) -> Result<ServiceSpecRollbackConfig, JinxError> {
+    let failure_action = match config.failure_action {
+        None => None,
+        Some(JinxFailureAction::Continue) => {
+            Some(ServiceSpecRollbackConfigFailureActionEnum::CONTINUE)
+        }
+        Some(JinxFailureAction::Pause) => Some(ServiceSpecRollbackConfigFailureActionEnum::PAUSE),
+        Some(JinxFailureAction::Rollback) => {
+            return Err(JinxError::InvalidConfig(
+                "rollback_config failure_action cannot be rollback".to_string(),
+            ))
+        }
     };
+    let order = config.order.map(|order| match order {
+        JinxUpdateOrder::StopFirst => ServiceSpecRollbackConfigOrderEnum::STOP_FIRST,
+        JinxUpdateOrder::StartFirst => ServiceSpecRollbackConfigOrderEnum::START_FIRST,
+    });
 
-    println!("Created container: {:?}", container_id.id);
+    Ok(ServiceSpecRollbackConfig {
+        parallelism: config.parallelism,
+        delay: config.delay.as_deref().map(parse_duration).transpose()?,
+        failure_action,
+        monitor: config.monitor.as_deref().map(parse_duration).transpose()?,
+        max_failure_ratio: config.max_failure_ratio,
+        order,
+    })
+}
 
-    match client
-        .start_container::<String>(&container_id.id, None)
-        .await
+// returns the container health config of the JinxHealthcheck
+fn get_health_config(healthcheck: &JinxHealthcheck) -> Result<HealthConfig, JinxError> {
+    if healthcheck.command.trim().is_empty() {
+        return Err(JinxError::InvalidConfig(
+            "healthcheck command must not be empty".to_string(),
+        ));
+    }
+
+    Ok(HealthConfig {
+        test: Some(vec!["CMD-SHELL".to_string(), healthcheck.command.to_string()]),
+        interval: healthcheck.interval.as_deref().map(parse_duration).transpose()?,
+        timeout: healthcheck.timeout.as_deref().map(parse_duration).transpose()?,
+        retries: healthcheck.retries,
+        start_period: healthcheck.start_period.as_deref().map(parse_duration).transpose()?,
+    })
+}
+
+// returns the parsed nano cpus and memory bytes of the JinxResources
+fn parse_resources(resources: &JinxResources) -> Result<(Option<i64>, Option<i64>), JinxError> {
+    let nano_cpus = resources.cpus.as_deref().map(parse_cpus).transpose()?;
+    let memory_bytes = resources.memory.as_deref().map(parse_memory).transpose()?;
+
+    Ok((nano_cpus, memory_bytes))
+}
+
+// returns the task resources of the JinxServiceResources
+fn get_task_resources(resources: &JinxServiceResources) -> Result<TaskSpecResources, JinxError> {
+    let limits = resources.limits.as_ref().map(parse_resources).transpose()?;
+    let reservations = resources
+        .reservations
+        .as_ref()
+        .map(parse_resources)
+        .transpose()?;
+
+    // reservations cannot exceed limits
+    if let (Some((limit_cpus, limit_memory)), Some((reserved_cpus, reserved_memory))) =
+        (limits, reservations)
     {
-        Ok(none) => none,
-        Err(err) => log_exit!("[DOCKER] Failed to start image", err),
+        if let (Some(limit), Some(reserved)) = (limit_cpus, reserved_cpus) {
+            if reserved > limit {
+                return Err(JinxError::InvalidConfig(
+                    "reserved cpus must not exceed the cpu limit".to_string(),
+                ));
+            }
+        }
+        if let (Some(limit), Some(reserved)) = (limit_memory, reserved_memory) {
+            if reserved > limit {
+                return Err(JinxError::InvalidConfig(
+                    "reserved memory must not exceed the memory limit".to_string(),
+                ));
+            }
+        }
+    }
+
+    Ok(TaskSpecResources {
+        limits: limits.map(|(nano_cpus, memory_bytes)| Limit {
+            nano_cp_us: nano_cpus,
+            memory_bytes,
+            ..Default::default()
+        }),
+        reservation: reservations.map(|(nano_cpus, memory_bytes)| ResourceObject {
+            nano_cp_us: nano_cpus,
+            memory_bytes,
+            ..Default::default()
+        }),
+    })
+}
+
+// returns the task placement of the JinxPlacement
+fn get_task_placement(placement: &JinxPlacement) -> Result<TaskSpecPlacement, JinxError> {
+    // constraints compare a node attribute with == or !=
+    let constraints = placement.constraints.clone().unwrap_or_default();
+    for constraint in constraints.iter() {
+        if !constraint.contains("==") && !constraint.contains("!=") {
+            return Err(JinxError::InvalidConfig(format!(
+                "placement constraint {} must use == or !=",
+                constraint
+            )));
+        }
+    }
+
+    let mut preferences = vec![];
+    for spread in placement.spread.clone().unwrap_or_default() {
+        if spread.trim().is_empty() {
+            return Err(JinxError::InvalidConfig(
+                "placement spread must not be empty".to_string(),
+            ));
+        }
+        preferences.push(TaskSpecPlacementPreferences {
+            spread: Some(TaskSpecPlacementSpread {
+                spread_descriptor: Some(spread),
+            }),
+        });
+    }
+
+    if let Some(max_replicas) = placement.max_replicas_per_node {
+        if max_replicas < 0 {
+            return Err(JinxError::InvalidConfig(format!(
+                "max_replicas_per_node must not be negative, got {}",
+                max_replicas
+            )));
+        }
+    }
+
+    Ok(TaskSpecPlacement {
+        constraints: Some(constraints),
+        preferences: Some(preferences),
+        max_replicas: placement.max_replicas_per_node,
+        ..Default::default()
+    })
+}
+
+// creates the service, or updates it in place when it already exists
+async fn _create_service(
+    client: Docker,
+    jinx_service: &JinxService,
+    name: String,
+) -> Result<(), JinxError> {
+    let mut spec = get_service_spec(jinx_service, &name)?;
+
+    // check for an existing service
+    let existing = match client.inspect_service(&name, None).await {
+        Ok(service) => service,
+        Err(bollard::errors::Error::DockerResponseNotFoundError { .. }) => {
+            let service = client.create_service(spec, None).await?;
+            let service_id = match service.id {
+                Some(id) => id,
+                None => "".to_string(),
+            };
+
+            println!("Jinx service created: {}", service_id);
+
+            return Ok(());
+        }
+        Err(err) => return Err(err.into()),
     };
 
-    println!("Started container: {:?}", container_id.id);
+    let version = existing.version.and_then(|v| v.index).unwrap_or(0);
+    let existing_spec = existing.spec.unwrap_or_default();
+
+    // keep the running replica count when none is configured
+    if jinx_service.replicas.is_none() {
+        let existing_replicas = existing_spec
+            .mode
+            .as_ref()
+            .and_then(|m| m.replicated.as_ref())
+            .and_then(|r| r.replicas);
+        let replicated = spec.mode.as_mut().and_then(|m| m.replicated.as_mut());
+
+        if let (Some(replicated), Some(replicas)) = (replicated, existing_replicas) {
+            replicated.replicas = Some(replicas);
+        }
+    }
+
+    // force a rolling update so rebuilt images with the same tag are redeployed
+    let force_update = existing_spec
+        .task_template
+        .as_ref()
+        .and_then(|t| t.force_update)
+        .unwrap_or(0);
+    if let Some(task_template) = spec.task_template.as_mut() {
+        task_template.force_update = Some(force_update + 1);
+    }
+
+    let options = UpdateServiceOptions {
+        version,
+        ..Default::default()
+    };
+
+    client.update_service(&name, spec, options, None).await?;
+
+    println!("Jinx service updated: {}", name);
+
+    Ok(())
 }
 
-async fn _create_service(client: Docker, jinx_service: &JinxService, name: String) {
+// returns the ServiceSpec for the JinxService
+fn get_service_spec(jinx_service: &JinxService, name: &str) -> Result<ServiceSpec, JinxError> {
     // define network to attach service
     let networks = vec![NetworkAttachmentConfig {
         target: Some("jinx_network".to_string()),
@@ -188,6 +820,10 @@ async fn _create_service(client: Docker, jinx_service: &JinxService, name: Strin
     }];
 
     // define service ports
+    let endpoint_mode = match jinx_service.endpoint_mode {
+        Some(JinxEndpointMode::Dnsrr) => EndpointSpecModeEnum::DNSRR,
+        _ => EndpointSpecModeEnum::VIP,
+    };
     let mut ports = vec![];
     if name.contains("jinx-proxy") {
         ports.push(EndpointPortConfig {
@@ -200,6 +836,16 @@ async fn _create_service(client: Docker, jinx_service: &JinxService, name: Strin
             published_port: Some(443),
             ..Default::default()
         });
+    } else if endpoint_mode == EndpointSpecModeEnum::DNSRR {
+        // dnsrr has no ingress routing mesh, so ports publish on each task's node
+        if let Some(published_port) = jinx_service.published_port {
+            ports.push(EndpointPortConfig {
+                target_port: Some(jinx_service.image_port),
+                published_port: Some(published_port),
+                publish_mode: Some(EndpointPortConfigPublishModeEnum::HOST),
+                ..Default::default()
+            });
+        }
     } else {
         ports.push(EndpointPortConfig {
             target_port: Some(jinx_service.image_port),
@@ -209,8 +855,8 @@ async fn _create_service(client: Docker, jinx_service: &JinxService, name: Strin
     }
 
     let endpoint_spec = EndpointSpec {
+        mode: Some(endpoint_mode),
         ports: Some(ports),
-        ..Default::default()
     };
 
     // define mounts
@@ -220,6 +866,12 @@ async fn _create_service(client: Docker, jinx_service: &JinxService, name: Strin
 
         for mount in image_volumes.iter() {
             let split: Vec<&str> = mount.split(':').collect();
+            if split.len() < 2 {
+                return Err(JinxError::InvalidConfig(format!(
+                    "image_volumes entry must be source:target, got {}",
+                    mount
+                )));
+            }
             let m = Mount {
                 source: Some(split[0].to_string()),
                 target: Some(split[1].to_string()),
@@ -242,6 +894,12 @@ async fn _create_service(client: Docker, jinx_service: &JinxService, name: Strin
         let image_secrets = jinx_service.image_secrets.clone().unwrap();
         for secret in image_secrets.iter() {
             let split: Vec<&str> = secret.split(':').collect();
+            if split.len() < 2 {
+                return Err(JinxError::InvalidConfig(format!(
+                    "image_secrets entry must be name:id, got {}",
+                    secret
+                )));
+            }
             let s = TaskSpecContainerSpecSecrets {
                 secret_name: Some(split[0].to_string()),
                 secret_id: Some(split[1].to_string()),
@@ -258,34 +916,288 @@ async fn _create_service(client: Docker, jinx_service: &JinxService, name: Strin
 
     // define service
     let service = ServiceSpec {
-        name: Some(name),
-        mode: Some(ServiceSpecMode {
-            replicated: Some(ServiceSpecModeReplicated { replicas: Some(1) }),
-            ..Default::default()
-        }),
+        name: Some(name.to_string()),
+        mode: Some(get_service_mode(jinx_service)?),
         task_template: Some(TaskSpec {
             container_spec: Some(TaskSpecContainerSpec {
                 image: Some(jinx_service.image_name.to_string()),
                 mounts: Some(mounts),
                 env: Some(envs.clone()),
                 secrets: Some(secrets),
+                health_check: jinx_service
+                    .healthcheck
+                    .as_ref()
+                    .map(get_health_config)
+                    .transpose()?,
                 ..Default::default()
             }),
+            resources: jinx_service
+                .resources
+                .as_ref()
+                .map(get_task_resources)
+                .transpose()?,
+            placement: jinx_service
+                .placement
+                .as_ref()
+                .map(get_task_placement)
+                .transpose()?,
             ..Default::default()
         }),
         networks: Some(networks),
         endpoint_spec: Some(endpoint_spec),
+        update_config: jinx_service
+            .update_config
+            .as_ref()
+            .map(get_update_config)
+            .transpose()?,
+        rollback_config: jinx_service
+            .rollback_config
+            .as_ref()
+            .map(get_rollback_config)
+            .transpose()?,
         ..Default::default()
     };
 
-    let service = match client.create_service(service, None).await {
-        Ok(svc) => svc,
-        Err(err) => log_exit!("[DOCKER] Failed to create jinx service", err),
-    };
-    let service_id = match service.id {
-        Some(id) => id,
-        None => "".to_string(),
-    };
+    Ok(service)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_image_tag_to_latest() {
+        assert_eq!(
+            get_image_tag("certbot/certbot").unwrap(),
+            ("certbot/certbot", "latest")
+        );
+        assert_eq!(get_image_tag("nginx:alpine").unwrap(), ("nginx", "alpine"));
+        assert_eq!(
+            get_image_tag("registry:5000/certbot/dns-route53").unwrap(),
+            ("registry:5000/certbot/dns-route53", "latest")
+        );
+        assert_eq!(
+            get_image_tag("registry:5000/nginx:1.25").unwrap(),
+            ("registry:5000/nginx", "1.25")
+        );
+        assert_eq!(
+            get_image_tag("nginx@sha256:0123abcd").unwrap(),
+            ("nginx", "sha256:0123abcd")
+        );
+    }
+
+    #[test]
+    fn rejects_unqualified_images() {
+        for image in ["", "nginx:", ":alpine", "nginx@"] {
+            assert!(get_image_tag(image).is_err(), "{} parsed", image);
+        }
+    }
+
+    // returns a service named web
+    fn get_test_service() -> JinxService {
+        JinxService {
+            name: "web".to_string(),
+            image_name: "web".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn builds_replicated_and_global_modes() {
+        let spec = get_service_spec(&get_test_service(), "web").unwrap();
+        assert_eq!(spec.mode.unwrap().replicated.unwrap().replicas, Some(1));
+
+        let jinx_service = JinxService {
+            mode: Some(JinxServiceMode::Replicated),
+            replicas: Some(3),
+            ..get_test_service()
+        };
+        let spec = get_service_spec(&jinx_service, "web").unwrap();
+        assert_eq!(spec.mode.unwrap().replicated.unwrap().replicas, Some(3));
+
+        let jinx_service = JinxService {
+            mode: Some(JinxServiceMode::Global),
+            ..get_test_service()
+        };
+        let mode = get_service_spec(&jinx_service, "web")
+            .unwrap()
+            .mode
+            .unwrap();
+        assert_eq!(mode.global, Some(HashMap::new()));
+        assert_eq!(mode.replicated, None);
+    }
 
-    println!("Jinx service created: {}", service_id);
+    #[test]
+    fn rejects_invalid_replicas() {
+        let jinx_services = [
+            JinxService {
+                replicas: Some(-1),
+                ..get_test_service()
+            },
+            JinxService {
+                mode: Some(JinxServiceMode::Global),
+                replicas: Some(2),
+                ..get_test_service()
+            },
+        ];
+        for jinx_service in jinx_services.iter() {
+            assert!(get_service_spec(jinx_service, "web").is_err());
+        }
+    }
+
+    #[test]
+    fn builds_update_and_rollback_configs() {
+        let config = JinxUpdateConfig {
+            parallelism: Some(2),
+            delay: Some("10s".to_string()),
+            failure_action: Some(JinxFailureAction::Pause),
+            monitor: Some("1m30s".to_string()),
+            max_failure_ratio: Some(0.25),
+            order: Some(JinxUpdateOrder::StartFirst),
+        };
+
+        assert_eq!(
+            get_update_config(&config).unwrap(),
+            ServiceSpecUpdateConfig {
+                parallelism: Some(2),
+                delay: Some(10_000_000_000),
+                failure_action: Some(ServiceSpecUpdateConfigFailureActionEnum::PAUSE),
+                monitor: Some(90_000_000_000),
+                max_failure_ratio: Some(0.25),
+                order: Some(ServiceSpecUpdateConfigOrderEnum::START_FIRST),
+            }
+        );
+        assert_eq!(
+            get_rollback_config(&config).unwrap(),
+            ServiceSpecRollbackConfig {
+                parallelism: Some(2),
+                delay: Some(10_000_000_000),
+                failure_action: Some(ServiceSpecRollbackConfigFailureActionEnum::PAUSE),
+                monitor: Some(90_000_000_000),
+                max_failure_ratio: Some(0.25),
+                order: Some(ServiceSpecRollbackConfigOrderEnum::START_FIRST),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_update_configs() {
+        let rollback = JinxUpdateConfig {
+            failure_action: Some(JinxFailureAction::Rollback),
+            ..Default::default()
+        };
+        assert_eq!(
+            get_update_config(&rollback).unwrap().failure_action,
+            Some(ServiceSpecUpdateConfigFailureActionEnum::ROLLBACK)
+        );
+        // a rollback cannot roll back
+        assert!(get_rollback_config(&rollback).is_err());
+
+        let delay = JinxUpdateConfig {
+            delay: Some("10".to_string()),
+            ..Default::default()
+        };
+        assert!(get_update_config(&delay).is_err());
+        assert!(get_rollback_config(&delay).is_err());
+    }
+
+    #[test]
+    fn builds_task_resources() {
+        let resources = JinxServiceResources {
+            limits: Some(JinxResources {
+                cpus: Some("1.5".to_string()),
+                memory: Some("1g".to_string()),
+            }),
+            reservations: Some(JinxResources {
+                cpus: Some("0.5".to_string()),
+                memory: None,
+            }),
+        };
+
+        let task_resources = get_task_resources(&resources).unwrap();
+        let limits = task_resources.limits.unwrap();
+        assert_eq!(limits.nano_cp_us, Some(1_500_000_000));
+        assert_eq!(limits.memory_bytes, Some(1024 * 1024 * 1024));
+        let reservation = task_resources.reservation.unwrap();
+        assert_eq!(reservation.nano_cp_us, Some(500_000_000));
+        assert_eq!(reservation.memory_bytes, None);
+    }
+
+    #[test]
+    fn rejects_reservations_above_limits() {
+        let over_limit = |limit: JinxResources, reservation: JinxResources| JinxServiceResources {
+            limits: Some(limit),
+            reservations: Some(reservation),
+        };
+        let resources = [
+            over_limit(
+                JinxResources {
+                    cpus: Some("1".to_string()),
+                    memory: None,
+                },
+                JinxResources {
+                    cpus: Some("2".to_string()),
+                    memory: None,
+                },
+            ),
+            over_limit(
+                JinxResources {
+                    cpus: None,
+                    memory: Some("512m".to_string()),
+                },
+                JinxResources {
+                    cpus: None,
+                    memory: Some("1g".to_string()),
+                },
+            ),
+        ];
+        for resources in resources.iter() {
+            assert!(get_task_resources(resources).is_err());
+        }
+    }
+
+    #[test]
+    fn builds_task_placement() {
+        let placement = JinxPlacement {
+            constraints: Some(vec![
+                "node.role==manager".to_string(),
+                "node.labels.edge != false".to_string(),
+            ]),
+            spread: Some(vec!["node.labels.zone".to_string()]),
+            max_replicas_per_node: Some(2),
+        };
+
+        let task_placement = get_task_placement(&placement).unwrap();
+        assert_eq!(task_placement.constraints, placement.constraints);
+        assert_eq!(
+            task_placement.preferences,
+            Some(vec![TaskSpecPlacementPreferences {
+                spread: Some(TaskSpecPlacementSpread {
+                    spread_descriptor: Some("node.labels.zone".to_string()),
+                }),
+            }])
+        );
+        assert_eq!(task_placement.max_replicas, Some(2));
+    }
+
+    #[test]
+    fn rejects_invalid_placement() {
+        let placements = [
+            JinxPlacement {
+                constraints: Some(vec!["node.role=manager".to_string()]),
+                ..Default::default()
+            },
+            JinxPlacement {
+                spread: Some(vec![" ".to_string()]),
+                ..Default::default()
+            },
+            JinxPlacement {
+                max_replicas_per_node: Some(-1),
+                ..Default::default()
+            },
+        ];
+        for placement in placements.iter() {
+            assert!(get_task_placement(placement).is_err());
+        }
+    }
 }
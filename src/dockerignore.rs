@@ -0,0 +1,220 @@
+use glob::{MatchOptions, Pattern};
+
+use crate::error::JinxError;
+
+// files the docker daemon always needs in the build context
+const ALWAYS_INCLUDED: [&str; 2] = ["Dockerfile", ".dockerignore"];
+
+// glob options matching docker's filepath.Match behaviour
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+// A single .dockerignore rule
+#[derive(Debug, Clone)]
+struct IgnorePattern {
+    pattern: Pattern,
+    exception: bool,
+}
+
+// Struct that matches context paths against .dockerignore rules
+#[derive(Debug, Clone, Default)]
+pub struct DockerIgnore {
+    patterns: Vec<IgnorePattern>,
+}
+
+impl DockerIgnore {
+    // returns a DockerIgnore parsed from .dockerignore lines
+    pub fn new(lines: &[String]) -> Result<DockerIgnore, JinxError> {
+        let mut patterns = vec![];
+
+        for line in lines.iter() {
+            // skip blank lines and comments
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // lines starting with ! re-include matching paths
+            let (exception, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest.trim()),
+                None => (false, line),
+            };
+
+            // patterns are relative to the context root
+            let cleaned = clean_path(line);
+            if cleaned.is_empty() {
+                continue;
+            }
+
+            let pattern = match Pattern::new(&cleaned) {
+                Ok(pattern) => pattern,
+                Err(err) => {
+                    return Err(JinxError::InvalidConfig(format!(
+                        ".dockerignore pattern {} is invalid: {}",
+                        line, err
+                    )))
+                }
+            };
+
+            patterns.push(IgnorePattern { pattern, exception });
+        }
+
+        Ok(DockerIgnore { patterns })
+    }
+
+    // returns true if any rule re-includes paths
+    pub fn has_exceptions(&self) -> bool {
+        self.patterns.iter().any(|p| p.exception)
+    }
+
+    // returns true if the context relative path should be left out of the tar
+    pub fn is_excluded(&self, path: &str) -> bool {
+        let path = clean_path(path);
+        if ALWAYS_INCLUDED.contains(&path.as_str()) {
+            return false;
+        }
+
+        // the last matching rule wins
+        let mut excluded = false;
+        for p in self.patterns.iter() {
+            // only rules that could change the current result need checking
+            if p.exception != excluded {
+                continue;
+            }
+
+            if matches_path_or_parent(&p.pattern, &path) {
+                excluded = !p.exception;
+            }
+        }
+
+        excluded
+    }
+}
+
+// returns true if the pattern matches the path or one of its parent directories
+fn matches_path_or_parent(pattern: &Pattern, path: &str) -> bool {
+    if pattern.matches_with(path, MATCH_OPTIONS) {
+        return true;
+    }
+
+    path.match_indices('/')
+        .any(|(i, _)| pattern.matches_with(&path[..i], MATCH_OPTIONS))
+}
+
+// returns the path with ".", "..", duplicate and leading "/" removed
+fn clean_path(path: &str) -> String {
+    let mut parts: Vec<&str> = vec![];
+
+    for part in path.split('/') {
+        match part {
+            "" | "." => continue,
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dockerignore(lines: &[&str]) -> DockerIgnore {
+        let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        DockerIgnore::new(&lines).unwrap()
+    }
+
+    #[test]
+    fn skips_blank_lines_and_comments() {
+        let ignore = dockerignore(&["", "  ", "# target", "*.log"]);
+
+        assert!(!ignore.is_excluded("target"));
+        assert!(ignore.is_excluded("debug.log"));
+    }
+
+    #[test]
+    fn matches_from_the_context_root() {
+        let ignore = dockerignore(&["/target", "./node_modules", "*.log"]);
+
+        assert!(ignore.is_excluded("target"));
+        assert!(ignore.is_excluded("node_modules"));
+        assert!(ignore.is_excluded("debug.log"));
+        // * does not cross directories
+        assert!(!ignore.is_excluded("logs/debug.log"));
+        assert!(!ignore.is_excluded("src/target"));
+    }
+
+    #[test]
+    fn matches_nested_paths() {
+        let ignore = dockerignore(&["docs/*.md", "*/temp"]);
+
+        assert!(ignore.is_excluded("docs/README.md"));
+        assert!(!ignore.is_excluded("docs/api/README.md"));
+        assert!(!ignore.is_excluded("README.md"));
+        assert!(ignore.is_excluded("src/temp"));
+        assert!(!ignore.is_excluded("temp"));
+    }
+
+    #[test]
+    fn double_star_matches_any_depth() {
+        let ignore = dockerignore(&["**/*.log", "cache/**"]);
+
+        assert!(ignore.is_excluded("debug.log"));
+        assert!(ignore.is_excluded("logs/debug.log"));
+        assert!(ignore.is_excluded("a/b/c/debug.log"));
+        assert!(ignore.is_excluded("cache/a/b"));
+        assert!(!ignore.is_excluded("src/main.rs"));
+    }
+
+    #[test]
+    fn excludes_contents_of_matched_directories() {
+        let ignore = dockerignore(&["target"]);
+
+        assert!(ignore.is_excluded("target"));
+        assert!(ignore.is_excluded("target/debug/jinx"));
+        assert!(!ignore.is_excluded("targets"));
+    }
+
+    #[test]
+    fn exceptions_re_include_paths() {
+        let ignore = dockerignore(&["*.md", "!README.md"]);
+
+        assert!(ignore.has_exceptions());
+        assert!(ignore.is_excluded("CHANGELOG.md"));
+        assert!(!ignore.is_excluded("README.md"));
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let ignore = dockerignore(&["docs", "!docs/public", "docs/public/drafts"]);
+
+        assert!(ignore.is_excluded("docs/private.md"));
+        assert!(!ignore.is_excluded("docs/public/index.md"));
+        assert!(ignore.is_excluded("docs/public/drafts/next.md"));
+
+        let ignore = dockerignore(&["!README.md", "*.md"]);
+        assert!(ignore.is_excluded("README.md"));
+    }
+
+    #[test]
+    fn always_includes_dockerfile_and_dockerignore() {
+        let ignore = dockerignore(&["*", "Dockerfile", ".dockerignore"]);
+
+        assert!(!ignore.is_excluded("Dockerfile"));
+        assert!(!ignore.is_excluded(".dockerignore"));
+        assert!(ignore.is_excluded("src"));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let lines = vec!["[".to_string()];
+
+        assert!(DockerIgnore::new(&lines).is_err());
+    }
+}
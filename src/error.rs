@@ -0,0 +1,88 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+// Errors returned by jinx
+#[derive(Debug)]
+pub enum JinxError {
+    // docker daemon or api failures
+    Docker(bollard::errors::Error),
+    // a certificate could not be parsed
+    Certificate(String),
+    // a command run in a container failed or had nowhere to run
+    Command(String),
+    // the daemon failed to build or pull an image
+    Image(String),
+    // filesystem failures
+    Io(io::Error),
+    // jinx.json or jinx_conf.json failed to parse
+    ConfigParse(serde_json::Error),
+    // a config value parsed but is not valid
+    InvalidConfig(String),
+    // handlebars failed to render a template
+    TemplateRender(handlebars::RenderError),
+    // nginx -t rejected the rendered nginx.conf, with nginx's error lines
+    InvalidNginxConf(Vec<String>),
+    // failed to build or read a tar archive
+    Tar(io::Error),
+}
+
+impl fmt::Display for JinxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JinxError::Docker(err) => write!(f, "[DOCKER] {}", err),
+            JinxError::Certificate(msg) => write!(f, "[CERT] {}", msg),
+            JinxError::Command(msg) => write!(f, "[DOCKER] {}", msg),
+            JinxError::Image(msg) => write!(f, "[DOCKER] {}", msg),
+            JinxError::Io(err) => write!(f, "[IO] {}", err),
+            JinxError::ConfigParse(err) => write!(f, "[CONF] Failed to parse config: {}", err),
+            JinxError::InvalidConfig(msg) => write!(f, "[CONF] Invalid config: {}", msg),
+            JinxError::TemplateRender(err) => write!(f, "[NGINX] Failed to render template: {}", err),
+            JinxError::InvalidNginxConf(lines) => {
+                write!(f, "[NGINX] Invalid nginx.conf: {}", lines.join("\n"))
+            }
+            JinxError::Tar(err) => write!(f, "[TARGZ] {}", err),
+        }
+    }
+}
+
+impl error::Error for JinxError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            JinxError::Docker(err) => Some(err),
+            JinxError::Certificate(_) => None,
+            JinxError::Command(_) => None,
+            JinxError::Image(_) => None,
+            JinxError::Io(err) => Some(err),
+            JinxError::ConfigParse(err) => Some(err),
+            JinxError::InvalidConfig(_) => None,
+            JinxError::TemplateRender(err) => Some(err),
+            JinxError::InvalidNginxConf(_) => None,
+            JinxError::Tar(err) => Some(err),
+        }
+    }
+}
+
+impl From<bollard::errors::Error> for JinxError {
+    fn from(err: bollard::errors::Error) -> Self {
+        JinxError::Docker(err)
+    }
+}
+
+impl From<io::Error> for JinxError {
+    fn from(err: io::Error) -> Self {
+        JinxError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for JinxError {
+    fn from(err: serde_json::Error) -> Self {
+        JinxError::ConfigParse(err)
+    }
+}
+
+impl From<handlebars::RenderError> for JinxError {
+    fn from(err: handlebars::RenderError) -> Self {
+        JinxError::TemplateRender(err)
+    }
+}
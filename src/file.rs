@@ -1,7 +1,9 @@
 use dirs;
 use serde_derive::{Deserialize, Serialize};
+use std::io;
+use std::io::ErrorKind;
 
-use super::log_exit;
+use crate::error::JinxError;
 
 // Struct that contains Jinx home, Jinx configuration, and Nginx configuration paths
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -11,13 +13,23 @@ pub struct JinxFiles {
   pub nginx_conf: String,
   pub letsencrypt_conf: String,
   pub letsencrypt_www: String,
+  pub jinx_certs: String,
+  pub proxy_certs: String,
+  pub jinx_htpasswd: String,
+  pub htpasswd_dir: String,
+  pub client_ca_dir: String,
 }
 
 // returns JinxFiles
-pub fn get_jinx_files() -> JinxFiles {
+pub fn get_jinx_files() -> Result<JinxFiles, JinxError> {
   // get users home directory
   let home_dir = match dirs::home_dir() {
-    None => log_exit!("[JINX] Failed to get home directory"),
+    None => {
+      return Err(JinxError::Io(io::Error::new(
+        ErrorKind::NotFound,
+        "Failed to get home directory",
+      )))
+    }
     Some(dir) => dir,
   };
 
@@ -27,12 +39,22 @@ pub fn get_jinx_files() -> JinxFiles {
   let nginx_conf = format!("{}/nginx.conf", jinx_home);
   let letsencrypt_conf = format!("{}/letsencrypt/conf", jinx_home);
   let letsencrypt_www = format!("{}/letsencrypt/www", jinx_home);
+  let jinx_certs = format!("{}/certs", jinx_home);
+  let proxy_certs = format!("{}/proxy_certs", jinx_home);
+  let jinx_htpasswd = format!("{}/jinx_htpasswd.json", jinx_home);
+  let htpasswd_dir = format!("{}/htpasswd", jinx_home);
+  let client_ca_dir = format!("{}/client_ca", jinx_home);
 
-  JinxFiles {
+  Ok(JinxFiles {
     jinx_home,
     jinx_conf,
     nginx_conf,
     letsencrypt_conf,
     letsencrypt_www,
-  }
+    jinx_certs,
+    proxy_certs,
+    jinx_htpasswd,
+    htpasswd_dir,
+    client_ca_dir,
+  })
 }
@@ -0,0 +1,172 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::BufReader;
+use std::io::ErrorKind;
+
+use crate::conf::JinxConf;
+use crate::error::JinxError;
+use crate::file::get_jinx_files;
+
+// password hashes nginx can check on alpine
+const HASH_PREFIXES: [&str; 9] = [
+    "$apr1$", "$1$", "$2a$", "$2b$", "$2y$", "$5$", "$6$", "{SHA}", "{SSHA}",
+];
+
+// user groups of jinx_htpasswd.json, each mapping users to password hashes
+pub type JinxHtpasswd = BTreeMap<String, BTreeMap<String, String>>;
+
+// returns the user groups of jinx_htpasswd.json, empty when it does not exist
+pub fn get_jinx_htpasswd() -> Result<JinxHtpasswd, JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    let file = match fs::File::open(&jinx_files.jinx_htpasswd) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(JinxHtpasswd::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
+
+// writes the user groups to jinx_htpasswd.json
+pub fn write_jinx_htpasswd(jinx_htpasswd: &JinxHtpasswd) -> Result<(), JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    // ensure path exists
+    fs::create_dir_all(&jinx_files.jinx_home)?;
+
+    fs::write(
+        &jinx_files.jinx_htpasswd,
+        serde_json::to_string(jinx_htpasswd)?.as_bytes(),
+    )?;
+
+    Ok(())
+}
+
+// returns the proxy container path of the htpasswd file of a user group
+pub fn get_htpasswd_path(jinx_conf: &JinxConf, group: &str) -> String {
+    let secret = jinx_conf
+        .htpasswd_secrets
+        .iter()
+        .flatten()
+        .find(|s| s.group == group);
+
+    match secret {
+        Some(secret) => {
+            let name = secret.secret.split(':').next().unwrap_or(&secret.secret);
+            format!("/run/secrets/{}", name)
+        }
+        None => format!("/etc/nginx/htpasswd/{}", group),
+    }
+}
+
+// returns the user groups used by the services and routes of the JinxConf
+pub fn get_htpasswd_groups(jinx_conf: &JinxConf) -> BTreeSet<String> {
+    let mut groups = BTreeSet::new();
+
+    for jinx_service in jinx_conf.jinx_services.iter() {
+        let route_access = jinx_service.get_routes().into_iter().filter_map(|r| r.access);
+        for access in jinx_service.access.clone().into_iter().chain(route_access) {
+            if let Some(group) = access.basic_auth {
+                groups.insert(group);
+            }
+        }
+    }
+
+    groups
+}
+
+// returns the htpasswd file contents of a user group
+fn get_htpasswd_file(group: &str, users: &BTreeMap<String, String>) -> Result<String, JinxError> {
+    let mut contents = String::new();
+
+    for (user, hash) in users.iter() {
+        if user.is_empty() || user.contains(|c: char| c == ':' || c.is_whitespace()) {
+            return Err(JinxError::InvalidConfig(format!(
+                "htpasswd user {} of {} must not be empty or contain : or whitespace",
+                user, group
+            )));
+        }
+
+        // only hashes are stored, never passwords
+        if !HASH_PREFIXES.iter().any(|p| hash.starts_with(p))
+            || hash.contains(char::is_whitespace)
+        {
+            return Err(JinxError::InvalidConfig(format!(
+                "htpasswd user {} of {} needs an apr1, bcrypt, crypt, or SHA password hash",
+                user, group
+            )));
+        }
+
+        contents.push_str(&format!("{}:{}\n", user, hash));
+    }
+
+    Ok(contents)
+}
+
+// writes an htpasswd file per user group to the htpasswd dir, checking used groups exist
+pub fn write_htpasswd_files(jinx_conf: &JinxConf) -> Result<(), JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    let jinx_htpasswd = get_jinx_htpasswd()?;
+
+    // groups name files in the htpasswd dir
+    for group in jinx_htpasswd.keys() {
+        let valid = group.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+        if group.is_empty() || group.starts_with('.') || !valid {
+            return Err(JinxError::InvalidConfig(format!(
+                "htpasswd group {} must only contain letters, digits, -, _, and .",
+                group
+            )));
+        }
+    }
+
+    for group in get_htpasswd_groups(jinx_conf) {
+        match jinx_htpasswd.get(&group) {
+            Some(users) if !users.is_empty() => {}
+            _ => {
+                return Err(JinxError::InvalidConfig(format!(
+                    "basic_auth group {} has no users in jinx_htpasswd.json",
+                    group
+                )))
+            }
+        }
+    }
+
+    // check every file before changing the dir, groups kept in secrets stay off the host
+    let mut files = vec![];
+    for (group, users) in jinx_htpasswd.iter() {
+        let contents = get_htpasswd_file(group, users)?;
+        if !get_htpasswd_path(jinx_conf, group).starts_with("/run/secrets/") {
+            files.push((group, contents));
+        }
+    }
+
+    // the dir is mounted into the proxy, so it must exist even when empty
+    fs::create_dir_all(&jinx_files.htpasswd_dir)?;
+
+    // files of deleted groups are kept, the loaded config may still use them until a reload
+    for (group, contents) in files {
+        fs::write(format!("{}/{}", jinx_files.htpasswd_dir, group), contents)?;
+    }
+
+    Ok(())
+}
+
+// writes an empty htpasswd file so nginx -t can load a group kept in a secret
+pub fn write_placeholder_htpasswd(group: &str) -> Result<String, JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    // nginx only reads the users on requests, so the check needs no hashes
+    let placeholder_dir = format!("{}.validate", jinx_files.htpasswd_dir);
+    fs::create_dir_all(&placeholder_dir)?;
+
+    let path = format!("{}/{}", placeholder_dir, group);
+    fs::write(&path, "")?;
+
+    Ok(path)
+}
@@ -1,12 +1,18 @@
 pub mod cert;
 pub mod conf;
 pub mod docker;
+pub mod dockerignore;
+pub mod error;
 pub mod file;
+pub mod htpasswd;
 pub mod nginx;
 pub mod service;
 pub mod targz;
+pub mod units;
 
-// logs errors and exits
+pub use error::JinxError;
+
+// logs errors and exits, intended for binaries handling a JinxError
 #[macro_export]
 macro_rules! log_exit {
     ($($x:expr),+) => {
@@ -1,72 +1,1561 @@
+use bollard::Docker;
 use handlebars::Handlebars;
+use serde_derive::Serialize;
 use std::fs;
-use std::fs::File;
-use std::str;
+use std::io::ErrorKind;
+use std::net::IpAddr;
 
-use super::log_exit;
-use crate::conf::JinxConf;
+use crate::cert::{write_client_ca_files, write_placeholder_certificate, write_proxy_certificates};
+use crate::conf::{JinxConf, JinxTlsProfile};
+use crate::docker::{exec_in_service, reload_jinx_proxy, run_image_to_completion};
+use crate::error::JinxError;
 use crate::file::get_jinx_files;
+use crate::htpasswd::{
+    get_htpasswd_groups, get_htpasswd_path, write_htpasswd_files, write_placeholder_htpasswd,
+};
+use crate::service::{
+    get_jinx_proxy_volumes, JinxAccess, JinxBalanceMethod, JinxClientAuth, JinxCustomCert,
+    JinxEndpointMode, JinxForwardAuth, JinxHsts, JinxRoute, JinxService, JinxVerifyClient,
+};
+
+// Struct that contains the data rendered into nginx.hbs
+#[derive(Debug, Serialize)]
+struct NginxTemplateData<'a> {
+    #[serde(flatten)]
+    jinx_conf: &'a JinxConf,
+    upstreams: Vec<NginxUpstream>,
+    limit_zones: Vec<String>,
+    servers: Vec<NginxServer>,
+    websocket: bool,
+    tls: NginxTls,
+}
+
+// Struct that contains the ssl settings of a tls profile
+#[derive(Debug, Serialize)]
+struct NginxTls {
+    protocols: &'static str,
+    ciphers: Option<&'static str>,
+    prefer_server_ciphers: &'static str,
+    session_tickets: &'static str,
+}
+
+// Struct that contains the upstream block of a service
+#[derive(Debug, Serialize)]
+struct NginxUpstream {
+    name: String,
+    // e.g. "least_conn" or "hash $request_uri consistent", round robin when unset
+    method: Option<String>,
+    // dnsrr task ips change, so nginx re-resolves them
+    resolve: bool,
+    servers: Vec<String>,
+    keepalive: Option<u32>,
+}
+
+// Struct that contains a server block shared by the services of a domain
+#[derive(Debug, Serialize)]
+struct NginxServer {
+    domain: String,
+    server_names: String,
+    ssl_certificate: String,
+    ssl_certificate_key: String,
+    // services sharing a domain may copy the same custom cert into their own dirs
+    #[serde(skip)]
+    custom_cert: Option<JinxCustomCert>,
+    access_log: String,
+    https: bool,
+    https_redirect: bool,
+    hsts: Option<String>,
+    ocsp_stapling: bool,
+    // client certificate verification
+    client_ca: Option<String>,
+    verify_client: Option<&'static str>,
+    verify_depth: Option<u32>,
+    locations: Vec<NginxLocation>,
+    auth_locations: Vec<NginxAuthLocation>,
+}
+
+impl NginxServer {
+    // returns the first setting that differs from another service's server block
+    fn get_mismatch(&self, other: &NginxServer) -> Option<&'static str> {
+        if self.server_names != other.server_names {
+            return Some("domain_aliases and include_www");
+        }
+        if self.https != other.https || self.https_redirect != other.https_redirect {
+            return Some("https and https_redirect");
+        }
+        if self.custom_cert != other.custom_cert
+            || (self.custom_cert.is_none() && self.ssl_certificate != other.ssl_certificate)
+        {
+            return Some("custom_cert");
+        }
+        if self.hsts != other.hsts {
+            return Some("hsts");
+        }
+        if self.ocsp_stapling != other.ocsp_stapling {
+            return Some("ocsp_stapling");
+        }
+        if self.client_ca != other.client_ca
+            || self.verify_client != other.verify_client
+            || self.verify_depth != other.verify_depth
+        {
+            return Some("client_auth");
+        }
+
+        None
+    }
+}
+
+// Struct that contains a location proxied to a service upstream
+#[derive(Debug, Serialize)]
+struct NginxLocation {
+    location: String,
+    upstream: String,
+    rewrite: Option<String>,
+    websocket: bool,
+    // clears the Connection header so upstream connections are reused
+    keepalive: bool,
+    // e.g. "zone=app-jinx-req burst=20 nodelay"
+    limit_req: Option<String>,
+    // e.g. "app-jinx-conn 10"
+    limit_conn: Option<String>,
+    allow: Vec<String>,
+    deny: Vec<String>,
+    // basic auth realm
+    auth_basic: Option<String>,
+    auth_basic_user_file: Option<String>,
+    // internal location of the forward auth subrequest
+    auth_request: Option<String>,
+    auth_headers: Vec<NginxAuthHeader>,
+    // named location redirecting to the login url
+    auth_login: Option<String>,
+    // e.g. "X-Client-DN", only set on https
+    client_dn_header: Option<String>,
+    read_timeout: Option<String>,
+    send_timeout: Option<String>,
+}
+
+// Struct that contains an auth response header passed upstream
+#[derive(Debug, Clone, Serialize)]
+struct NginxAuthHeader {
+    header: String,
+    // e.g. "$jinx_auth_x_user"
+    variable: String,
+    // e.g. "$upstream_http_x_user"
+    upstream_variable: String,
+}
+
+// Struct that contains the internal locations of a service's forward auth
+#[derive(Debug, Serialize)]
+struct NginxAuthLocation {
+    location: String,
+    upstream: String,
+    path: String,
+    login_location: Option<String>,
+    login_redirect: Option<String>,
+}
+
+// returns the ssl settings of the JinxConf tls profile, following the mozilla guidelines
+fn get_tls(jinx_conf: &JinxConf) -> NginxTls {
+    let profile = jinx_conf.tls_profile.unwrap_or(JinxTlsProfile::Intermediate);
+    let session_tickets = if jinx_conf.ssl_session_tickets.unwrap_or(false) {
+        "on"
+    } else {
+        "off"
+    };
+
+    match profile {
+        JinxTlsProfile::Modern => NginxTls {
+            protocols: "TLSv1.3",
+            ciphers: None,
+            prefer_server_ciphers: "off",
+            session_tickets,
+        },
+        JinxTlsProfile::Intermediate => NginxTls {
+            protocols: "TLSv1.2 TLSv1.3",
+            ciphers: Some("ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-RSA-AES128-GCM-SHA256:ECDHE-ECDSA-AES256-GCM-SHA384:ECDHE-RSA-AES256-GCM-SHA384:ECDHE-ECDSA-CHACHA20-POLY1305:ECDHE-RSA-CHACHA20-POLY1305:DHE-RSA-AES128-GCM-SHA256:DHE-RSA-AES256-GCM-SHA384:DHE-RSA-CHACHA20-POLY1305"),
+            prefer_server_ciphers: "off",
+            session_tickets,
+        },
+        JinxTlsProfile::Legacy => NginxTls {
+            protocols: "TLSv1 TLSv1.1 TLSv1.2 TLSv1.3",
+            ciphers: Some("ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-RSA-AES128-GCM-SHA256:ECDHE-ECDSA-AES256-GCM-SHA384:ECDHE-RSA-AES256-GCM-SHA384:ECDHE-ECDSA-CHACHA20-POLY1305:ECDHE-RSA-CHACHA20-POLY1305:DHE-RSA-AES128-GCM-SHA256:DHE-RSA-AES256-GCM-SHA384:DHE-RSA-CHACHA20-POLY1305:ECDHE-ECDSA-AES128-SHA256:ECDHE-RSA-AES128-SHA256:ECDHE-ECDSA-AES128-SHA:ECDHE-RSA-AES128-SHA:ECDHE-ECDSA-AES256-SHA384:ECDHE-RSA-AES256-SHA384:ECDHE-ECDSA-AES256-SHA:ECDHE-RSA-AES256-SHA:DHE-RSA-AES128-SHA256:DHE-RSA-AES256-SHA256:AES128-GCM-SHA256:AES256-GCM-SHA384:AES128-SHA256:AES256-SHA256:AES128-SHA:AES256-SHA:DES-CBC3-SHA"),
+            prefer_server_ciphers: "on",
+            session_tickets,
+        },
+    }
+}
 
 // returns str of the nginx.hbs template
 fn get_nginx_template() -> &'static str {
     // load template from binary
-    let nginx_bytes = include_bytes!("./templates/nginx.hbs");
-    let nginx_template = str::from_utf8(nginx_bytes).expect("[NGINX] Failed to convert template");
+    include_str!("./templates/nginx.hbs")
+}
+
+// returns the nginx location of a route, e.g. "/api/" or "~ ^/v[0-9]+/"
+fn get_location(route: &JinxRoute) -> Result<String, JinxError> {
+    let path = route.path.trim();
+
+    if route.regex.unwrap_or(false) {
+        if path.is_empty() {
+            return Err(JinxError::InvalidConfig(
+                "route regex must not be empty".to_string(),
+            ));
+        }
+        if route.strip_prefix.unwrap_or(false) {
+            return Err(JinxError::InvalidConfig(format!(
+                "route {} cannot strip the prefix of a regex",
+                path
+            )));
+        }
+
+        if path.contains(char::is_control) {
+            return Err(JinxError::InvalidConfig(format!(
+                "route regex {} must not contain control characters",
+                path
+            )));
+        }
+
+        // quoted so braces in quantifiers parse, nginx unescapes \\ and \"
+        let quoted = path.replace('\\', "\\\\").replace('"', "\\\"");
+        return Ok(format!("~ \"{}\"", quoted));
+    }
+
+    let invalid = |c: char| c.is_whitespace() || ";{}\"'".contains(c);
+    if !path.starts_with('/') || path.contains(invalid) {
+        return Err(JinxError::InvalidConfig(format!(
+            "route path {} must start with / and not contain whitespace, quotes, ; or braces",
+            path
+        )));
+    }
+
+    Ok(path.to_string())
+}
+
+// returns the rewrite removing the route prefix, when the route strips it
+fn get_rewrite(route: &JinxRoute) -> Option<String> {
+    if !route.strip_prefix.unwrap_or(false) {
+        return None;
+    }
+
+    // escape regex characters in the prefix
+    let prefix = route.path.trim().trim_end_matches('/');
+    let mut escaped = String::new();
+    for c in prefix.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    Some(format!("^{}/?(.*)$ /$1 break", escaped))
+}
+
+// returns the nginx time, checking it is a number with an optional unit such as "60s"
+fn get_nginx_time(time: &Option<String>) -> Result<Option<String>, JinxError> {
+    let time = match time {
+        Some(time) => time.trim(),
+        None => return Ok(None),
+    };
+
+    let number_len = time.find(|c: char| !c.is_ascii_digit()).unwrap_or(time.len());
+    let unit = &time[number_len..];
+    let units = ["", "ms", "s", "m", "h", "d", "w", "M", "y"];
+    if number_len == 0 || !units.contains(&unit) {
+        return Err(JinxError::InvalidConfig(format!(
+            "nginx time {} is not valid",
+            time
+        )));
+    }
+
+    Ok(Some(time.to_string()))
+}
+
+// returns the Strict-Transport-Security header value of the JinxHsts
+fn get_hsts(hsts: &JinxHsts) -> Result<String, JinxError> {
+    let max_age = hsts.max_age.unwrap_or(63072000);
+    let include_subdomains = hsts.include_subdomains.unwrap_or(false);
+    let preload = hsts.preload.unwrap_or(false);
+
+    // browsers only accept preload lists meeting these requirements
+    if preload && (!include_subdomains || max_age < 31536000) {
+        return Err(JinxError::InvalidConfig(
+            "hsts preload requires include_subdomains and a max_age of at least 31536000"
+                .to_string(),
+        ));
+    }
+
+    let mut value = format!("max-age={}", max_age);
+    if include_subdomains {
+        value.push_str("; includeSubDomains");
+    }
+    if preload {
+        value.push_str("; preload");
+    }
+
+    Ok(value)
+}
+
+// returns the upstream block of the JinxService, with any static servers
+fn get_upstream(jinx_service: &JinxService) -> Result<NginxUpstream, JinxError> {
+    let load_balancing = jinx_service.load_balancing.clone().unwrap_or_default();
+    let resolve = jinx_service.endpoint_mode == Some(JinxEndpointMode::Dnsrr);
+
+    let method = match (load_balancing.method, &load_balancing.hash_key) {
+        (Some(JinxBalanceMethod::Hash), Some(hash_key)) if !hash_key.trim().is_empty() => {
+            let invalid = |c: char| c.is_whitespace() || ";{}\"'".contains(c);
+            if hash_key.trim().contains(invalid) {
+                return Err(JinxError::InvalidConfig(format!(
+                    "hash_key {} of {} must not contain whitespace, quotes, ; or braces",
+                    hash_key.trim(),
+                    jinx_service.name
+                )));
+            }
+
+            let consistent = if load_balancing.hash_consistent.unwrap_or(false) {
+                " consistent"
+            } else {
+                ""
+            };
+            Some(format!("hash {}{}", hash_key.trim(), consistent))
+        }
+        (Some(JinxBalanceMethod::Hash), _) => {
+            return Err(JinxError::InvalidConfig(format!(
+                "hash load_balancing of {} needs a hash_key",
+                jinx_service.name
+            )))
+        }
+        (_, Some(_)) => {
+            return Err(JinxError::InvalidConfig(format!(
+                "hash_key of {} needs the hash load_balancing method",
+                jinx_service.name
+            )))
+        }
+        (Some(JinxBalanceMethod::LeastConn), None) => Some("least_conn".to_string()),
+        (Some(JinxBalanceMethod::IpHash), None) => Some("ip_hash".to_string()),
+        (Some(JinxBalanceMethod::RoundRobin), None) | (None, None) => None,
+    };
+
+    // failure parameters apply to every server
+    let mut parameters = String::new();
+    if let Some(max_fails) = load_balancing.max_fails {
+        parameters.push_str(&format!(" max_fails={}", max_fails));
+    }
+    if let Some(fail_timeout) = get_nginx_time(&load_balancing.fail_timeout)? {
+        parameters.push_str(&format!(" fail_timeout={}", fail_timeout));
+    }
+
+    let swarm_server = format!("{}-jinx:{}", jinx_service.name, jinx_service.image_port);
+    let mut servers = vec![if resolve {
+        format!("{} resolve{}", swarm_server, parameters)
+    } else {
+        format!("{}{}", swarm_server, parameters)
+    }];
+
+    for server in load_balancing.servers.unwrap_or_default() {
+        let server = server.trim();
+        if server.is_empty() || server.contains(|c: char| c.is_whitespace() || c == ';') {
+            return Err(JinxError::InvalidConfig(format!(
+                "load_balancing server {} of {} must be a host:port",
+                server, jinx_service.name
+            )));
+        }
+        servers.push(format!("{}{}", server, parameters));
+    }
+
+    Ok(NginxUpstream {
+        name: format!("{}-jinx-upstream", jinx_service.name),
+        method,
+        resolve,
+        servers,
+        keepalive: load_balancing.keepalive,
+    })
+}
+
+// returns the trimmed header name, checking it only has letters, digits, and -
+fn get_header_name(header: &str) -> Result<String, JinxError> {
+    let header = header.trim();
+
+    if header.is_empty() || !header.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(JinxError::InvalidConfig(format!(
+            "header {} is not a valid header name",
+            header
+        )));
+    }
 
-    nginx_template
+    Ok(header.to_string())
+}
+
+// returns the nginx variable keying a limit, the client ip or a request header
+fn get_limit_key(key_header: &Option<String>) -> Result<String, JinxError> {
+    let header = match key_header {
+        Some(header) => get_header_name(header)?,
+        None => return Ok("$binary_remote_addr".to_string()),
+    };
+
+    // requests without the header have an empty key and are not limited
+    Ok(format!("$http_{}", header.to_lowercase().replace('-', "_")))
+}
+
+// returns the limit_req_zone and limit_conn_zone directives of the services
+fn get_limit_zones(jinx_services: &[JinxService]) -> Result<Vec<String>, JinxError> {
+    let mut zones = vec![];
+
+    for jinx_service in jinx_services.iter() {
+        if let Some(rate_limit) = &jinx_service.rate_limit {
+            if rate_limit.rate == 0 {
+                return Err(JinxError::InvalidConfig(format!(
+                    "rate_limit rate of {} must be at least 1",
+                    jinx_service.name
+                )));
+            }
+
+            zones.push(format!(
+                "limit_req_zone {} zone={}-jinx-req:10m rate={}r/s",
+                get_limit_key(&rate_limit.key_header)?,
+                jinx_service.name,
+                rate_limit.rate
+            ));
+        }
+
+        if let Some(conn_limit) = &jinx_service.conn_limit {
+            if conn_limit.max_connections == 0 {
+                return Err(JinxError::InvalidConfig(format!(
+                    "conn_limit max_connections of {} must be at least 1",
+                    jinx_service.name
+                )));
+            }
+
+            zones.push(format!(
+                "limit_conn_zone {} zone={}-jinx-conn:10m",
+                get_limit_key(&conn_limit.key_header)?,
+                jinx_service.name
+            ));
+        }
+    }
+
+    Ok(zones)
+}
+
+// returns the limit_req arguments of the JinxService
+fn get_limit_req(jinx_service: &JinxService) -> Option<String> {
+    let rate_limit = jinx_service.rate_limit.as_ref()?;
+
+    let mut limit_req = format!("zone={}-jinx-req", jinx_service.name);
+    if let Some(burst) = rate_limit.burst {
+        limit_req.push_str(&format!(" burst={}", burst));
+    }
+    if rate_limit.nodelay.unwrap_or(false) {
+        limit_req.push_str(" nodelay");
+    }
+
+    Some(limit_req)
+}
+
+// returns the address or CIDR of an allow or deny rule, checking it parses
+fn get_access_address(address: &str) -> Result<String, JinxError> {
+    let address = address.trim();
+    let invalid = || {
+        JinxError::InvalidConfig(format!(
+            "access address {} must be all, an ip address, or a CIDR",
+            address
+        ))
+    };
+
+    if address == "all" {
+        return Ok(address.to_string());
+    }
+
+    let (ip, prefix) = match address.split_once('/') {
+        Some((ip, prefix)) => (ip, Some(prefix)),
+        None => (address, None),
+    };
+    let ip: IpAddr = ip.parse().map_err(|_| invalid())?;
+
+    if let Some(prefix) = prefix {
+        let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+        match prefix.parse::<u8>() {
+            Ok(prefix) if prefix <= max_prefix => {}
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(address.to_string())
+}
+
+// sets the allow, deny, and basic auth directives of a location
+fn set_access(
+    location: &mut NginxLocation,
+    jinx_conf: &JinxConf,
+    jinx_service: &JinxService,
+    access: &JinxAccess,
+) -> Result<(), JinxError> {
+    for address in access.allow.iter().flatten() {
+        location.allow.push(get_access_address(address)?);
+    }
+    for address in access.deny.iter().flatten() {
+        location.deny.push(get_access_address(address)?);
+    }
+
+    if let Some(group) = &access.basic_auth {
+        let realm = access.realm.clone().unwrap_or_else(|| jinx_service.name.to_string());
+        if realm.contains(|c: char| c == '"' || c == '\\' || c.is_control()) {
+            return Err(JinxError::InvalidConfig(format!(
+                "access realm {} must not contain quotes, backslashes, or control characters",
+                realm
+            )));
+        }
+
+        location.auth_basic = Some(realm);
+        location.auth_basic_user_file = Some(get_htpasswd_path(jinx_conf, group));
+    }
+
+    Ok(())
+}
+
+// returns the internal locations of the JinxService's forward auth
+fn get_auth_location(
+    jinx_conf: &JinxConf,
+    jinx_service: &JinxService,
+    forward_auth: &JinxForwardAuth,
+) -> Result<NginxAuthLocation, JinxError> {
+    let auth_service = jinx_conf
+        .jinx_services
+        .iter()
+        .find(|s| s.name == forward_auth.service && s.name != jinx_service.name);
+    if auth_service.is_none() {
+        return Err(JinxError::InvalidConfig(format!(
+            "forward_auth service {} of {} must be another jinx service",
+            forward_auth.service, jinx_service.name
+        )));
+    }
+
+    let path = forward_auth.path.clone().unwrap_or_else(|| "/".to_string());
+    if !path.starts_with('/') || path.contains(|c: char| c.is_whitespace() || c == ';') {
+        return Err(JinxError::InvalidConfig(format!(
+            "forward_auth path {} of {} must start with / and not contain whitespace or ;",
+            path, jinx_service.name
+        )));
+    }
+
+    let login_redirect = match &forward_auth.login_url {
+        Some(login_url) => {
+            let invalid = login_url.contains(|c: char| c.is_whitespace() || "\";{}".contains(c));
+            if !login_url.starts_with("http") || invalid {
+                return Err(JinxError::InvalidConfig(format!(
+                    "forward_auth login_url {} of {} must be an http url",
+                    login_url, jinx_service.name
+                )));
+            }
+
+            let separator = if login_url.contains('?') { '&' } else { '?' };
+            Some(format!(
+                "{}{}rd=$scheme://$host$request_uri",
+                login_url, separator
+            ))
+        }
+        None => None,
+    };
+
+    Ok(NginxAuthLocation {
+        location: format!("/_jinx_auth/{}", jinx_service.name),
+        upstream: format!("{}-jinx-upstream", forward_auth.service),
+        path,
+        login_location: login_redirect
+            .as_ref()
+            .map(|_| format!("@{}-jinx-login", jinx_service.name)),
+        login_redirect,
+    })
+}
+
+// returns the auth response headers passed upstream
+fn get_auth_headers(forward_auth: &JinxForwardAuth) -> Result<Vec<NginxAuthHeader>, JinxError> {
+    let mut auth_headers = vec![];
+
+    for header in forward_auth.response_headers.iter().flatten() {
+        let header = get_header_name(header)?;
+        let name = header.to_lowercase().replace('-', "_");
+        auth_headers.push(NginxAuthHeader {
+            header,
+            variable: format!("$jinx_auth_{}", name),
+            upstream_variable: format!("$upstream_http_{}", name),
+        });
+    }
+
+    Ok(auth_headers)
+}
+
+// returns the server block of the JinxService, without locations
+fn get_server(jinx_service: &JinxService) -> Result<NginxServer, JinxError> {
+    let (ssl_certificate, ssl_certificate_key) = jinx_service.get_ssl_paths()?;
+
+    Ok(NginxServer {
+        domain: jinx_service.domain.to_string(),
+        server_names: jinx_service.get_server_names().join(" "),
+        ssl_certificate,
+        ssl_certificate_key,
+        custom_cert: jinx_service.custom_cert.clone(),
+        access_log: jinx_service.image_name.to_string(),
+        https: jinx_service.https,
+        https_redirect: jinx_service.https_redirect,
+        hsts: jinx_service.hsts.as_ref().map(get_hsts).transpose()?,
+        ocsp_stapling: jinx_service.ocsp_stapling.unwrap_or(false),
+        client_ca: jinx_service.get_client_ca_path()?,
+        verify_client: jinx_service.client_auth.as_ref().map(|c| match c.verify {
+            Some(JinxVerifyClient::Optional) => "optional",
+            Some(JinxVerifyClient::On) | None => "on",
+        }),
+        verify_depth: jinx_service
+            .client_auth
+            .as_ref()
+            .map(|c| c.verify_depth.unwrap_or(1)),
+        locations: vec![],
+        auth_locations: vec![],
+    })
+}
+
+// returns the server blocks, merging services that share a domain
+fn get_servers(jinx_conf: &JinxConf) -> Result<Vec<NginxServer>, JinxError> {
+    let mut servers: Vec<NginxServer> = vec![];
+
+    for jinx_service in jinx_conf.jinx_services.iter() {
+        let server = get_server(jinx_service)?;
+
+        // find the server block for the domain
+        let index = match servers.iter().position(|s| s.domain == server.domain) {
+            Some(index) => {
+                if let Some(setting) = servers[index].get_mismatch(&server) {
+                    return Err(JinxError::InvalidConfig(format!(
+                        "services sharing {} must use the same {}",
+                        server.domain, setting
+                    )));
+                }
+                index
+            }
+            None => {
+                servers.push(server);
+                servers.len() - 1
+            }
+        };
+
+        let websocket = jinx_service.websocket.unwrap_or(false);
+        let keepalive = jinx_service
+            .load_balancing
+            .as_ref()
+            .is_some_and(|l| l.keepalive.is_some());
+        let limit_req = get_limit_req(jinx_service);
+        let limit_conn = jinx_service
+            .conn_limit
+            .as_ref()
+            .map(|c| format!("{}-jinx-conn {}", jinx_service.name, c.max_connections));
+        let read_timeout = get_nginx_time(&jinx_service.proxy_read_timeout)?;
+        let send_timeout = get_nginx_time(&jinx_service.proxy_send_timeout)?;
+
+        // check requests with the auth service first
+        let auth_location = match &jinx_service.forward_auth {
+            Some(forward_auth) => Some(get_auth_location(jinx_conf, jinx_service, forward_auth)?),
+            None => None,
+        };
+        let client_dn_header = match &jinx_service.client_auth {
+            Some(JinxClientAuth {
+                dn_header: Some(dn_header),
+                ..
+            }) => Some(get_header_name(dn_header)?),
+            _ => None,
+        };
+        let auth_headers = match &jinx_service.forward_auth {
+            Some(forward_auth) => get_auth_headers(forward_auth)?,
+            None => vec![],
+        };
+
+        // add a location per route, rejecting routes already taken
+        for route in jinx_service.get_routes().iter() {
+            let location = get_location(route)?;
+            let server = &mut servers[index];
+
+            if server.locations.iter().any(|l| l.location == location) {
+                return Err(JinxError::InvalidConfig(format!(
+                    "route {} of {} conflicts with another service on {}",
+                    route.path, jinx_service.name, jinx_service.domain
+                )));
+            }
+
+            let mut nginx_location = NginxLocation {
+                location,
+                upstream: format!("{}-jinx-upstream", jinx_service.name),
+                rewrite: get_rewrite(route),
+                websocket,
+                keepalive: keepalive && !websocket,
+                limit_req: limit_req.clone(),
+                limit_conn: limit_conn.clone(),
+                allow: vec![],
+                deny: vec![],
+                auth_basic: None,
+                auth_basic_user_file: None,
+                auth_request: auth_location.as_ref().map(|a| a.location.clone()),
+                auth_headers: auth_headers.clone(),
+                auth_login: auth_location.as_ref().and_then(|a| a.login_location.clone()),
+                client_dn_header: client_dn_header.clone(),
+                read_timeout: read_timeout.clone(),
+                send_timeout: send_timeout.clone(),
+            };
+
+            // route access replaces the service's
+            if let Some(access) = route.access.as_ref().or(jinx_service.access.as_ref()) {
+                set_access(&mut nginx_location, jinx_conf, jinx_service, access)?;
+            }
+
+            server.locations.push(nginx_location);
+        }
+
+        if let Some(auth_location) = auth_location {
+            servers[index].auth_locations.push(auth_location);
+        }
+    }
+
+    // a host name can only belong to one server block
+    for (i, server) in servers.iter().enumerate() {
+        for name in server.server_names.split(' ') {
+            let duplicate = servers
+                .iter()
+                .skip(i + 1)
+                .find(|s| s.server_names.split(' ').any(|n| n == name));
+            if let Some(duplicate) = duplicate {
+                return Err(JinxError::InvalidConfig(format!(
+                    "{} is served by both {} and {}",
+                    name, server.domain, duplicate.domain
+                )));
+            }
+        }
+    }
+
+    Ok(servers)
 }
 
 // returns a rendered string of the nginx.hbs template
-pub fn render_template(jinx_conf: &JinxConf) -> String {
-    // create handlebars instance
-    let handlebars = Handlebars::new();
+pub fn render_template(jinx_conf: &JinxConf) -> Result<String, JinxError> {
+    // create handlebars instance, nginx.conf is not html so values are not escaped
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
 
     // load template
     let nginx_template = get_nginx_template();
 
+    // build template data
+    let data = NginxTemplateData {
+        jinx_conf,
+        upstreams: jinx_conf
+            .jinx_services
+            .iter()
+            .map(get_upstream)
+            .collect::<Result<Vec<NginxUpstream>, JinxError>>()?,
+        limit_zones: get_limit_zones(&jinx_conf.jinx_services)?,
+        servers: get_servers(jinx_conf)?,
+        websocket: jinx_conf
+            .jinx_services
+            .iter()
+            .any(|s| s.websocket.unwrap_or(false)),
+        tls: get_tls(jinx_conf),
+    };
+
     // render template
-    let rendered_nginx = handlebars
-        .render_template(nginx_template, &jinx_conf)
-        .expect("[NGINX] Failed to render template");
+    let rendered_nginx = handlebars.render_template(nginx_template, &data)?;
 
-    rendered_nginx
+    Ok(rendered_nginx)
 }
 
-// writes JinxConf to nginx_conf file
-pub fn write_nginx_conf(jinx_conf: &JinxConf) {
-    // create handlebars instance
-    let handlebars = Handlebars::new();
+// checks the rendered nginx.conf with nginx -t in a throwaway nginx:alpine container
+pub async fn validate_nginx_conf(
+    client: Docker,
+    jinx_conf: &JinxConf,
+    rendered_nginx: &str,
+) -> Result<(), JinxError> {
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
 
-    // load template
-    let nginx_template = get_nginx_template();
+    // mount the files the proxy mounts, with the rendered config in place of the current one
+    let candidate = format!("{}.validate", jinx_files.nginx_conf);
+    let mut binds: Vec<String> = get_jinx_proxy_volumes(&jinx_files)
+        .into_iter()
+        .map(|volume| {
+            if volume.ends_with(":/etc/nginx/nginx.conf") {
+                format!("{}:/etc/nginx/nginx.conf:ro", candidate)
+            } else {
+                format!("{}:ro", volume)
+            }
+        })
+        .collect();
+
+    for jinx_service in jinx_conf.jinx_services.iter().filter(|s| s.https) {
+        // secrets are only readable by the proxy, so stand in matching placeholders
+        if !jinx_service.get_cert_secrets().is_empty() {
+            let (cert, key) = jinx_service.get_ssl_paths()?;
+            let (placeholder_cert, placeholder_key) =
+                write_placeholder_certificate(&jinx_service.name)?;
+            binds.push(format!("{}:{}", placeholder_cert, cert));
+            binds.push(format!("{}:{}", placeholder_key, key));
+        }
+    }
+
+    // placeholders for client CA bundles kept in secrets
+    for jinx_service in jinx_conf.jinx_services.iter() {
+        let ca_secret = jinx_service.client_auth.as_ref().and_then(|c| c.ca_secret.as_ref());
+        if let (Some(_), Some(client_ca)) = (ca_secret, jinx_service.get_client_ca_path()?) {
+            let (placeholder_ca, _) = write_placeholder_certificate(&jinx_service.name)?;
+            binds.push(format!("{}:{}:ro", placeholder_ca, client_ca));
+        }
+    }
+
+    // placeholders for htpasswd files kept in secrets, at their secret paths
+    for group in get_htpasswd_groups(jinx_conf) {
+        let path = get_htpasswd_path(jinx_conf, &group);
+        if path.starts_with("/run/secrets/") {
+            let placeholder = write_placeholder_htpasswd(&group)?;
+            binds.push(format!("{}:{}:ro", placeholder, path));
+        }
+    }
+
+    fs::write(&candidate, rendered_nginx)?;
+    let volumes = binds.iter().map(|b| b.as_str()).collect();
+    let cmds = vec!["nginx", "-t"];
+
+    // upstream hosts only resolve on jinx_network, so point them at localhost
+    let extra_hosts = jinx_conf
+        .jinx_services
+        .iter()
+        .map(|s| format!("{}-jinx:127.0.0.1", s.name))
+        .collect();
+
+    let result = run_image_to_completion(
+        client,
+        "nginx:alpine",
+        volumes,
+        None,
+        Some(cmds),
+        extra_hosts,
+    )
+    .await;
+
+    // the candidate and htpasswd placeholders are only needed by the check
+    fs::remove_file(&candidate)?;
+    match fs::remove_dir_all(format!("{}.validate", jinx_files.htpasswd_dir)) {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+    let (status_code, output) = result?;
+
+    if status_code == 0 {
+        return Ok(());
+    }
+
+    // surface nginx's error lines, or all output when none are tagged
+    let lines: Vec<String> = output.lines().map(|l| l.trim().to_string()).collect();
+    let errors: Vec<String> = lines
+        .iter()
+        .filter(|l| ["[emerg]", "[alert]", "[crit]", "[error]"].iter().any(|t| l.contains(t)))
+        .cloned()
+        .collect();
+
+    if errors.is_empty() {
+        Err(JinxError::InvalidNginxConf(lines))
+    } else {
+        Err(JinxError::InvalidNginxConf(errors))
+    }
+}
+
+// writes JinxConf to nginx_conf file, once nginx -t accepts it
+pub async fn write_nginx_conf(client: Docker, jinx_conf: &JinxConf) -> Result<(), JinxError> {
+    // render template
+    let rendered_nginx = render_template(jinx_conf)?;
+
+    // certificates, htpasswd files and client CA bundles must exist for nginx -t
+    write_proxy_certificates(jinx_conf)?;
+    write_htpasswd_files(jinx_conf)?;
+    write_client_ca_files(jinx_conf)?;
+
+    // validate before replacing the current config
+    validate_nginx_conf(client, jinx_conf, &rendered_nginx).await?;
+
+    // get jinx files
+    let jinx_files = get_jinx_files()?;
+
+    // write file in place, keeping the inode bind mounted by the proxy
+    fs::write(jinx_files.nginx_conf, rendered_nginx)?;
+
+    Ok(())
+}
 
+// writes JinxConf to nginx_conf file and reloads the running proxy to apply it
+pub async fn reload_nginx_conf(client: Docker, jinx_conf: &JinxConf) -> Result<(), JinxError> {
     // get jinx files
-    let jinx_files = get_jinx_files();
+    let jinx_files = get_jinx_files()?;
 
-    // create output files
-    let output_file = match File::create(jinx_files.nginx_conf) {
-        Err(err) => log_exit!("[NGINX] Failed to create nginx_conf", err),
-        Ok(file) => file,
+    // keep the config the proxy has loaded, to restore it if the proxy rejects the new one
+    let previous = match fs::read(&jinx_files.nginx_conf) {
+        Ok(previous) => Some(previous),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
     };
 
-    // write file
-    handlebars
-        .render_template_to_write(&nginx_template, &jinx_conf, output_file)
-        .expect("[NGINX] Failed to write nginx_conf");
+    write_nginx_conf(client.clone(), jinx_conf).await?;
+
+    // nginx -s reload succeeds even when the master rejects the config, so check it in place first
+    let result = match exec_in_service(client.clone(), "jinx-proxy", vec!["nginx", "-t"]).await {
+        Ok(_) => reload_jinx_proxy(client).await,
+        Err(err) => Err(err),
+    };
+
+    if let Err(err) = result {
+        if let Some(previous) = previous {
+            fs::write(&jinx_files.nginx_conf, previous)?;
+        }
+        return Err(err);
+    }
+
+    Ok(())
 }
 
 // writes the Dockerfile for jinx_proxy
-pub fn write_nginx_dockerfile() {
+pub fn write_nginx_dockerfile() -> Result<(), JinxError> {
     // get jinx files
-    let jinx_files = get_jinx_files();
+    let jinx_files = get_jinx_files()?;
 
     // load template from binary
     let dockerfile_bytes = include_bytes!("./templates/Dockerfile");
 
     // write file
     fs::write(
-        &format!("{}/Dockerfile", jinx_files.jinx_home),
-        &dockerfile_bytes,
-    )
-    .expect("[CONF] Failed to write jinx Dockerfile");
+        format!("{}/Dockerfile", jinx_files.jinx_home),
+        dockerfile_bytes,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::JinxHtpasswdSecret;
+    use crate::service::{JinxConnLimit, JinxLoadBalancing, JinxRateLimit};
+
+    // returns an https service of example.com
+    fn get_test_service(name: &str) -> JinxService {
+        JinxService {
+            name: name.to_string(),
+            domain: "example.com".to_string(),
+            image_name: name.to_string(),
+            https: true,
+            https_redirect: true,
+            ..Default::default()
+        }
+    }
+
+    // returns a route of the path
+    fn get_test_route(path: &str) -> JinxRoute {
+        JinxRoute {
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // returns a JinxConf of the services
+    fn get_test_conf(jinx_services: Vec<JinxService>) -> JinxConf {
+        JinxConf {
+            jinx_services,
+            ..Default::default()
+        }
+    }
+
+    // checks the JinxConf is rejected before rendering
+    fn assert_invalid(jinx_conf: &JinxConf) {
+        match render_template(jinx_conf) {
+            Err(JinxError::InvalidConfig(_)) => {}
+            result => panic!("expected InvalidConfig, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn serves_domain_aliases() {
+        let mut web = get_test_service("web");
+        web.domain_aliases = Some(vec!["example.org".to_string()]);
+        web.include_www = Some(false);
+
+        let rendered = render_template(&get_test_conf(vec![web])).unwrap();
+
+        assert_eq!(
+            rendered
+                .matches("server_name example.com example.org;")
+                .count(),
+            2
+        );
+        assert!(
+            rendered.contains("ssl_certificate /etc/letsencrypt/live/example.com/fullchain.pem;")
+        );
+    }
+
+    #[test]
+    fn merges_services_sharing_a_domain() {
+        let mut api = get_test_service("api");
+        api.routes = Some(vec![JinxRoute {
+            strip_prefix: Some(true),
+            ..get_test_route("/api/")
+        }]);
+
+        let rendered = render_template(&get_test_conf(vec![get_test_service("web"), api])).unwrap();
+
+        // one redirect and one https server
+        assert_eq!(
+            rendered
+                .matches("server_name example.com www.example.com;")
+                .count(),
+            2
+        );
+        assert!(rendered.contains("    location / {\n      proxy_pass http://web-jinx-upstream;\n"));
+        assert!(rendered.contains(
+            "    location /api/ {\n      rewrite ^/api/?(.*)$ /$1 break;\n      proxy_pass http://api-jinx-upstream;\n"
+        ));
+    }
+
+    #[test]
+    fn rejects_conflicting_routes() {
+        assert_invalid(&get_test_conf(vec![
+            get_test_service("web"),
+            get_test_service("api"),
+        ]));
+    }
+
+    #[test]
+    fn rejects_mismatched_shared_servers() {
+        let mut api = get_test_service("api");
+        api.routes = Some(vec![get_test_route("/api/")]);
+        api.https = false;
+
+        assert_invalid(&get_test_conf(vec![get_test_service("web"), api]));
+    }
+
+    #[test]
+    fn shares_custom_certificates_between_services() {
+        let custom_cert = JinxCustomCert {
+            cert_path: Some("/certs/fullchain.pem".to_string()),
+            key_path: Some("/certs/privkey.pem".to_string()),
+            ..Default::default()
+        };
+        let mut web = get_test_service("web");
+        web.custom_cert = Some(custom_cert.clone());
+        let mut api = get_test_service("api");
+        api.routes = Some(vec![get_test_route("/api/")]);
+        api.custom_cert = Some(custom_cert);
+
+        let rendered = render_template(&get_test_conf(vec![web.clone(), api.clone()])).unwrap();
+        assert!(rendered.contains("ssl_certificate /etc/jinx/certs/web/fullchain.pem;"));
+
+        // a different chain for the same domain is still a conflict
+        api.custom_cert = Some(JinxCustomCert {
+            cert_path: Some("/other/fullchain.pem".to_string()),
+            key_path: Some("/other/privkey.pem".to_string()),
+            ..Default::default()
+        });
+        assert_invalid(&get_test_conf(vec![web, api]));
+    }
+
+    #[test]
+    fn rejects_duplicate_server_names() {
+        let mut web = get_test_service("web");
+        web.domain_aliases = Some(vec!["example.org".to_string()]);
+        let mut org = get_test_service("org");
+        org.domain = "example.org".to_string();
+
+        assert_invalid(&get_test_conf(vec![web, org]));
+    }
+
+    #[test]
+    fn quotes_regex_locations() {
+        let route = JinxRoute {
+            regex: Some(true),
+            ..get_test_route(r#"^/a\.b"c{2}"#)
+        };
+        assert_eq!(get_location(&route).unwrap(), r#"~ "^/a\\.b\"c{2}""#);
+
+        for route in [
+            JinxRoute {
+                regex: Some(true),
+                ..get_test_route("")
+            },
+            JinxRoute {
+                regex: Some(true),
+                ..get_test_route("^/a\nb")
+            },
+            JinxRoute {
+                regex: Some(true),
+                strip_prefix: Some(true),
+                ..get_test_route("^/a/")
+            },
+        ] {
+            assert!(get_location(&route).is_err(), "{} parsed", route.path);
+        }
+    }
+
+    #[test]
+    fn rejects_directive_characters_in_paths() {
+        assert_eq!(get_location(&get_test_route(" /api/ ")).unwrap(), "/api/");
+
+        for path in ["api", "/a b", "/a;b", "/a{", "/a}", "/a\"b", "/a'b"] {
+            assert!(
+                get_location(&get_test_route(path)).is_err(),
+                "{} parsed",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn escapes_strip_prefix_rewrites() {
+        let route = JinxRoute {
+            strip_prefix: Some(true),
+            ..get_test_route("/v1.0+/")
+        };
+        assert_eq!(get_rewrite(&route).unwrap(), r"^/v1\.0\+/?(.*)$ /$1 break");
+        assert_eq!(get_rewrite(&get_test_route("/v1/")), None);
+    }
+
+    #[test]
+    fn proxies_websocket_upgrades() {
+        let mut web = get_test_service("web");
+        web.websocket = Some(true);
+        web.proxy_read_timeout = Some("3600s".to_string());
+        web.proxy_send_timeout = Some("1h".to_string());
+
+        let rendered = render_template(&get_test_conf(vec![web])).unwrap();
+
+        assert!(rendered.contains("map $http_upgrade $connection_upgrade {"));
+        assert!(rendered.contains(
+            "      proxy_set_header Upgrade $http_upgrade;\n      proxy_set_header Connection $connection_upgrade;\n"
+        ));
+        assert!(rendered.contains("      proxy_read_timeout 3600s;\n"));
+        assert!(rendered.contains("      proxy_send_timeout 1h;\n"));
+        assert!(!rendered.contains("proxy_set_header Connection \"\";"));
+    }
+
+    #[test]
+    fn skips_upgrades_without_websocket() {
+        let rendered = render_template(&get_test_conf(vec![get_test_service("web")])).unwrap();
+
+        assert!(!rendered.contains("$connection_upgrade"));
+        assert!(!rendered.contains("proxy_read_timeout"));
+    }
+
+    #[test]
+    fn rejects_invalid_proxy_timeouts() {
+        for timeout in ["", "s", "60x", "1.5s", "60 s"] {
+            let mut web = get_test_service("web");
+            web.proxy_read_timeout = Some(timeout.to_string());
+            assert_invalid(&get_test_conf(vec![web]));
+        }
+    }
+
+    #[test]
+    fn builds_hsts_headers() {
+        assert_eq!(get_hsts(&JinxHsts::default()).unwrap(), "max-age=63072000");
+
+        let hsts = JinxHsts {
+            max_age: Some(31536000),
+            include_subdomains: Some(true),
+            preload: Some(true),
+        };
+        assert_eq!(
+            get_hsts(&hsts).unwrap(),
+            "max-age=31536000; includeSubDomains; preload"
+        );
+    }
+
+    #[test]
+    fn rejects_hsts_preload_without_requirements() {
+        for (max_age, include_subdomains) in [(63072000, false), (86400, true)] {
+            let hsts = JinxHsts {
+                max_age: Some(max_age),
+                include_subdomains: Some(include_subdomains),
+                preload: Some(true),
+            };
+            assert!(get_hsts(&hsts).is_err());
+        }
+    }
+
+    #[test]
+    fn renders_tls_profiles() {
+        let mut web = get_test_service("web");
+        web.hsts = Some(JinxHsts::default());
+        web.ocsp_stapling = Some(true);
+        let mut jinx_conf = get_test_conf(vec![web]);
+
+        let rendered = render_template(&jinx_conf).unwrap();
+        assert!(rendered.contains("ssl_protocols TLSv1.2 TLSv1.3;"));
+        assert!(rendered.contains("ssl_ciphers \"ECDHE-ECDSA-AES128-GCM-SHA256:"));
+        assert!(rendered.contains("ssl_session_tickets off;"));
+        assert!(rendered.contains("ssl_stapling on;"));
+        assert!(
+            rendered.contains("add_header Strict-Transport-Security \"max-age=63072000\" always;")
+        );
+
+        jinx_conf.tls_profile = Some(JinxTlsProfile::Modern);
+        jinx_conf.ssl_session_tickets = Some(true);
+        let rendered = render_template(&jinx_conf).unwrap();
+        assert!(rendered.contains("ssl_protocols TLSv1.3;"));
+        assert!(!rendered.contains("ssl_ciphers"));
+        assert!(rendered.contains("ssl_session_tickets on;"));
+
+        jinx_conf.tls_profile = Some(JinxTlsProfile::Legacy);
+        let rendered = render_template(&jinx_conf).unwrap();
+        assert!(rendered.contains("ssl_protocols TLSv1 TLSv1.1 TLSv1.2 TLSv1.3;"));
+        assert!(rendered.contains("ssl_prefer_server_ciphers on;"));
+    }
+
+    // returns a service balanced with the load balancing settings
+    fn get_balanced_service(load_balancing: JinxLoadBalancing) -> JinxService {
+        JinxService {
+            load_balancing: Some(load_balancing),
+            ..get_test_service("web")
+        }
+    }
+
+    #[test]
+    fn builds_upstream_methods() {
+        let upstream = get_upstream(&get_test_service("web")).unwrap();
+        assert_eq!(upstream.name, "web-jinx-upstream");
+        assert_eq!(upstream.method, None);
+        assert_eq!(upstream.servers, vec!["web-jinx:8080"]);
+
+        let methods = [
+            (JinxBalanceMethod::RoundRobin, None),
+            (JinxBalanceMethod::LeastConn, Some("least_conn")),
+            (JinxBalanceMethod::IpHash, Some("ip_hash")),
+        ];
+        for (method, expected) in methods {
+            let jinx_service = get_balanced_service(JinxLoadBalancing {
+                method: Some(method),
+                ..Default::default()
+            });
+            let upstream = get_upstream(&jinx_service).unwrap();
+            assert_eq!(upstream.method.as_deref(), expected);
+        }
+
+        let jinx_service = get_balanced_service(JinxLoadBalancing {
+            method: Some(JinxBalanceMethod::Hash),
+            hash_key: Some(" $cookie_session ".to_string()),
+            hash_consistent: Some(true),
+            ..Default::default()
+        });
+        let upstream = get_upstream(&jinx_service).unwrap();
+        assert_eq!(
+            upstream.method.as_deref(),
+            Some("hash $cookie_session consistent")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_hash_keys() {
+        let hash_keys = [
+            (Some(JinxBalanceMethod::Hash), None),
+            (Some(JinxBalanceMethod::Hash), Some(" ")),
+            (Some(JinxBalanceMethod::Hash), Some("$uri;")),
+            (Some(JinxBalanceMethod::Hash), Some("$uri {")),
+            (Some(JinxBalanceMethod::LeastConn), Some("$uri")),
+            (None, Some("$uri")),
+        ];
+        for (method, hash_key) in hash_keys {
+            let jinx_service = get_balanced_service(JinxLoadBalancing {
+                method,
+                hash_key: hash_key.map(|k| k.to_string()),
+                ..Default::default()
+            });
+            assert!(
+                get_upstream(&jinx_service).is_err(),
+                "{:?} parsed",
+                hash_key
+            );
+        }
+    }
+
+    #[test]
+    fn adds_failure_parameters_to_every_server() {
+        let jinx_service = JinxService {
+            endpoint_mode: Some(JinxEndpointMode::Dnsrr),
+            ..get_balanced_service(JinxLoadBalancing {
+                max_fails: Some(3),
+                fail_timeout: Some("30s".to_string()),
+                servers: Some(vec!["10.0.0.5:8080".to_string()]),
+                ..Default::default()
+            })
+        };
+
+        let upstream = get_upstream(&jinx_service).unwrap();
+        assert!(upstream.resolve);
+        assert_eq!(
+            upstream.servers,
+            vec![
+                "web-jinx:8080 resolve max_fails=3 fail_timeout=30s",
+                "10.0.0.5:8080 max_fails=3 fail_timeout=30s"
+            ]
+        );
+
+        for server in ["", "10.0.0.5 8080", "10.0.0.5:8080;"] {
+            let jinx_service = get_balanced_service(JinxLoadBalancing {
+                servers: Some(vec![server.to_string()]),
+                ..Default::default()
+            });
+            assert!(get_upstream(&jinx_service).is_err(), "{} parsed", server);
+        }
+    }
+
+    #[test]
+    fn keeps_upstream_connections_alive() {
+        let jinx_service = get_balanced_service(JinxLoadBalancing {
+            keepalive: Some(16),
+            ..Default::default()
+        });
+
+        let rendered = render_template(&get_test_conf(vec![jinx_service])).unwrap();
+
+        assert!(rendered.contains("    keepalive 16;\n  }"));
+        assert!(rendered.contains("      proxy_set_header Connection \"\";\n"));
+    }
+
+    #[test]
+    fn builds_limit_zones() {
+        let mut web = get_test_service("web");
+        web.rate_limit = Some(JinxRateLimit {
+            rate: 10,
+            burst: Some(20),
+            nodelay: Some(true),
+            key_header: None,
+        });
+        web.conn_limit = Some(JinxConnLimit {
+            max_connections: 5,
+            key_header: Some("X-Api-Key".to_string()),
+        });
+        let jinx_conf = get_test_conf(vec![web]);
+
+        assert_eq!(
+            get_limit_zones(&jinx_conf.jinx_services).unwrap(),
+            vec![
+                "limit_req_zone $binary_remote_addr zone=web-jinx-req:10m rate=10r/s",
+                "limit_conn_zone $http_x_api_key zone=web-jinx-conn:10m"
+            ]
+        );
+
+        let rendered = render_template(&jinx_conf).unwrap();
+        assert!(rendered.contains("  limit_req_status 429;\n"));
+        assert!(rendered.contains("      limit_req zone=web-jinx-req burst=20 nodelay;\n"));
+        assert!(rendered.contains("      limit_conn web-jinx-conn 5;\n"));
+    }
+
+    #[test]
+    fn rejects_invalid_limits() {
+        let mut web = get_test_service("web");
+        web.rate_limit = Some(JinxRateLimit::default());
+        assert_invalid(&get_test_conf(vec![web]));
+
+        let mut web = get_test_service("web");
+        web.conn_limit = Some(JinxConnLimit::default());
+        assert_invalid(&get_test_conf(vec![web]));
+
+        for header in ["", "X Api", "X_Api", "$uri"] {
+            let mut web = get_test_service("web");
+            web.rate_limit = Some(JinxRateLimit {
+                rate: 1,
+                key_header: Some(header.to_string()),
+                ..Default::default()
+            });
+            assert_invalid(&get_test_conf(vec![web]));
+        }
+    }
+
+    #[test]
+    fn checks_access_addresses() {
+        for address in ["all", " 10.0.0.1 ", "10.0.0.0/8", "::1", "fd00::/128"] {
+            assert_eq!(get_access_address(address).unwrap(), address.trim());
+        }
+        for address in [
+            "",
+            "10.0.0",
+            "10.0.0.0/33",
+            "fd00::/129",
+            "10.0.0.0/",
+            "host",
+        ] {
+            assert!(get_access_address(address).is_err(), "{} parsed", address);
+        }
+    }
+
+    #[test]
+    fn renders_route_access() {
+        let mut web = get_test_service("web");
+        web.access = Some(JinxAccess {
+            allow: Some(vec!["10.0.0.0/8".to_string()]),
+            deny: Some(vec!["10.0.0.1".to_string()]),
+            ..Default::default()
+        });
+        web.routes = Some(vec![
+            get_test_route("/"),
+            JinxRoute {
+                access: Some(JinxAccess {
+                    basic_auth: Some("admins".to_string()),
+                    ..Default::default()
+                }),
+                ..get_test_route("/admin/")
+            },
+        ]);
+        let mut jinx_conf = get_test_conf(vec![web]);
+
+        let rendered = render_template(&jinx_conf).unwrap();
+        assert!(rendered.contains(
+            "    location / {\n      deny 10.0.0.1;\n      allow 10.0.0.0/8;\n      deny all;\n"
+        ));
+        // route access replaces the service's
+        assert!(rendered.contains(
+            "    location /admin/ {\n      auth_basic \"web\";\n      auth_basic_user_file /etc/nginx/htpasswd/admins;\n      proxy_pass"
+        ));
+
+        jinx_conf.htpasswd_secrets = Some(vec![JinxHtpasswdSecret {
+            group: "admins".to_string(),
+            secret: "admins_htpasswd:abc123".to_string(),
+        }]);
+        let rendered = render_template(&jinx_conf).unwrap();
+        assert!(rendered.contains("auth_basic_user_file /run/secrets/admins_htpasswd;"));
+    }
+
+    #[test]
+    fn rejects_invalid_access() {
+        let accesses = [
+            JinxAccess {
+                allow: Some(vec!["10.0.0.0/8;".to_string()]),
+                ..Default::default()
+            },
+            JinxAccess {
+                basic_auth: Some("admins".to_string()),
+                realm: Some("a\"b".to_string()),
+                ..Default::default()
+            },
+        ];
+        for access in accesses {
+            let mut web = get_test_service("web");
+            web.access = Some(access);
+            assert_invalid(&get_test_conf(vec![web]));
+        }
+    }
+
+    // returns a web service checked against an auth service
+    fn get_forward_auth_conf(forward_auth: JinxForwardAuth) -> JinxConf {
+        let mut web = get_test_service("web");
+        web.forward_auth = Some(forward_auth);
+        let mut auth = get_test_service("auth");
+        auth.domain = "auth.example.com".to_string();
+
+        get_test_conf(vec![web, auth])
+    }
+
+    #[test]
+    fn renders_forward_auth() {
+        let jinx_conf = get_forward_auth_conf(JinxForwardAuth {
+            service: "auth".to_string(),
+            path: Some("/verify".to_string()),
+            response_headers: Some(vec!["X-User".to_string()]),
+            login_url: Some("https://auth.example.com/login?app=web".to_string()),
+        });
+
+        let rendered = render_template(&jinx_conf).unwrap();
+        assert!(rendered.contains(
+            "      auth_request /_jinx_auth/web;\n      auth_request_set $jinx_auth_x_user $upstream_http_x_user;\n      error_page 401 = @web-jinx-login;\n"
+        ));
+        assert!(rendered.contains("      proxy_set_header X-User $jinx_auth_x_user;\n"));
+        assert!(rendered.contains(
+            "    location = /_jinx_auth/web {\n      internal;\n      proxy_pass http://auth-jinx-upstream/verify;\n"
+        ));
+        assert!(rendered.contains(
+            "    location @web-jinx-login {\n      return 302 https://auth.example.com/login?app=web&rd=$scheme://$host$request_uri;\n"
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_forward_auth() {
+        let forward_auths = [
+            JinxForwardAuth {
+                service: "missing".to_string(),
+                ..Default::default()
+            },
+            JinxForwardAuth {
+                service: "web".to_string(),
+                ..Default::default()
+            },
+            JinxForwardAuth {
+                service: "auth".to_string(),
+                path: Some("verify".to_string()),
+                ..Default::default()
+            },
+            JinxForwardAuth {
+                service: "auth".to_string(),
+                login_url: Some("/login".to_string()),
+                ..Default::default()
+            },
+            JinxForwardAuth {
+                service: "auth".to_string(),
+                response_headers: Some(vec!["X User".to_string()]),
+                ..Default::default()
+            },
+        ];
+        for forward_auth in forward_auths {
+            assert_invalid(&get_forward_auth_conf(forward_auth));
+        }
+    }
+
+    #[test]
+    fn renders_client_auth() {
+        let mut web = get_test_service("web");
+        web.client_auth = Some(JinxClientAuth {
+            ca_path: Some("/etc/ssl/ca.pem".to_string()),
+            verify: Some(JinxVerifyClient::Optional),
+            verify_depth: Some(2),
+            dn_header: Some("X-Client-DN".to_string()),
+            ..Default::default()
+        });
+
+        let rendered = render_template(&get_test_conf(vec![web])).unwrap();
+        assert!(rendered.contains(
+            "    ssl_client_certificate /etc/nginx/client_ca/web.pem;\n    ssl_verify_client optional;\n    ssl_verify_depth 2;\n"
+        ));
+        assert!(rendered.contains("      proxy_set_header X-Client-DN $ssl_client_s_dn;\n"));
+    }
+
+    #[test]
+    fn rejects_mismatched_client_auth() {
+        let mut web = get_test_service("web");
+        web.client_auth = Some(JinxClientAuth {
+            ca_path: Some("/etc/ssl/ca.pem".to_string()),
+            ..Default::default()
+        });
+        let mut api = get_test_service("api");
+        api.routes = Some(vec![get_test_route("/api/")]);
+
+        assert_invalid(&get_test_conf(vec![web, api]));
+    }
+
+    #[test]
+    fn caches_assets_without_taking_sibling_routes() {
+        let mut api = get_test_service("api");
+        api.routes = Some(vec![JinxRoute {
+            regex: Some(true),
+            ..get_test_route("^/v[0-9]{1,2}/")
+        }]);
+        let jinx_conf = JinxConf {
+            jinx_services: vec![get_test_service("web"), api],
+            ..Default::default()
+        };
+
+        let rendered = render_template(&jinx_conf).unwrap();
+
+        // a regex nested in location / would take /v1/app.js from the api route
+        assert!(!rendered.contains("      location "));
+        assert!(rendered.contains(
+            "    location ~ \"^/v[0-9]{1,2}/\" {\n      proxy_pass http://api-jinx-upstream;\n"
+        ));
+        assert_eq!(rendered.matches("expires $jinx_asset_expires;").count(), 2);
+    }
 }
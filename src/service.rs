@@ -1,10 +1,234 @@
+use serde::de::Error as _;
 use serde_derive::{Deserialize, Serialize};
 use std::env;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader};
 
-use super::log_exit;
-use crate::file::get_jinx_files;
+use crate::conf::get_jinx_conf;
+use crate::error::JinxError;
+use crate::file::{get_jinx_files, JinxFiles};
+use crate::targz::TarCompression;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, std::cmp::PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JinxServiceMode {
+  // runs the configured number of replicas
+  Replicated,
+  // runs one task on every node
+  Global,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, std::cmp::PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JinxFailureAction {
+  Continue,
+  Pause,
+  // only valid for update_config
+  Rollback,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, std::cmp::PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum JinxUpdateOrder {
+  StopFirst,
+  StartFirst,
+}
+
+// Struct that contains how swarm updates or rolls back the tasks of a service
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxUpdateConfig {
+  // tasks updated at the same time, 0 updates all at once
+  pub parallelism: Option<i64>,
+  // duration between updating groups of tasks, e.g. "10s"
+  pub delay: Option<String>,
+  pub failure_action: Option<JinxFailureAction>,
+  // duration to watch each task for failure after updating it, e.g. "30s"
+  pub monitor: Option<String>,
+  pub max_failure_ratio: Option<f64>,
+  pub order: Option<JinxUpdateOrder>,
+}
+
+// Struct that contains the container health check of a service
+// swarm only adds healthy tasks to the service VIP and DNS, so nginx needs no checks of its own
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxHealthcheck {
+  // shell command that exits 0 when the container is healthy
+  pub command: String,
+  // durations such as "30s"
+  pub interval: Option<String>,
+  pub timeout: Option<String>,
+  pub retries: Option<i64>,
+  pub start_period: Option<String>,
+}
+
+// Struct that contains an amount of cpu and memory for a service
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxResources {
+  // number of cpus, e.g. "0.5"
+  pub cpus: Option<String>,
+  // memory size, e.g. "512m"
+  pub memory: Option<String>,
+}
+
+// Struct that contains the resources a service is limited to and has reserved
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxServiceResources {
+  pub limits: Option<JinxResources>,
+  pub reservations: Option<JinxResources>,
+}
+
+// Struct that contains which swarm nodes run the tasks of a service
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxPlacement {
+  // e.g. "node.role==manager" or "node.labels.edge==true"
+  pub constraints: Option<Vec<String>>,
+  // node attributes to spread tasks evenly over, e.g. "node.labels.zone"
+  pub spread: Option<Vec<String>>,
+  pub max_replicas_per_node: Option<i64>,
+}
+
+// Struct that contains a request path routed to a service
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxRoute {
+  // path prefix such as "/api/", or a regex when regex is true
+  pub path: String,
+  // remove the path prefix before proxying, defaults to false
+  pub strip_prefix: Option<bool>,
+  // match path as a case sensitive regex, defaults to false
+  pub regex: Option<bool>,
+  // replaces the service's access for this route
+  pub access: Option<JinxAccess>,
+}
+
+// Struct that contains the ip and basic auth restrictions of a service or route
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxAccess {
+  // addresses or CIDRs such as "10.0.0.0/8", everything else is denied when set
+  pub allow: Option<Vec<String>>,
+  // addresses or CIDRs denied before allow is checked
+  pub deny: Option<Vec<String>>,
+  // user group of jinx_htpasswd.json allowed in with basic auth
+  pub basic_auth: Option<String>,
+  // defaults to the service name
+  pub realm: Option<String>,
+}
+
+// Struct that contains the Strict-Transport-Security header of a service
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxHsts {
+  // seconds, defaults to two years
+  pub max_age: Option<u64>,
+  pub include_subdomains: Option<bool>,
+  pub preload: Option<bool>,
+}
+
+// how the proxy reaches the tasks of a service
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, std::cmp::PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JinxEndpointMode {
+  // a virtual ip balanced by swarm, the default
+  Vip,
+  // dns round robin over the task ips, balanced by nginx
+  Dnsrr,
+}
+
+// nginx upstream balancing methods
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, std::cmp::PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JinxBalanceMethod {
+  RoundRobin,
+  LeastConn,
+  IpHash,
+  // requires hash_key
+  Hash,
+}
+
+// Struct that contains the nginx upstream settings of a service
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxLoadBalancing {
+  // defaults to round_robin
+  pub method: Option<JinxBalanceMethod>,
+  // e.g. "$request_uri" or "$cookie_session"
+  pub hash_key: Option<String>,
+  // ketama consistent hashing, keeps most keys in place when servers change
+  pub hash_consistent: Option<bool>,
+  pub max_fails: Option<u32>,
+  // e.g. "30s"
+  pub fail_timeout: Option<String>,
+  // idle connections kept open to the upstream per worker
+  pub keepalive: Option<u32>,
+  // extra host:port backends outside the swarm
+  pub servers: Option<Vec<String>>,
+}
+
+// Struct that contains the request rate limit of a service
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxRateLimit {
+  // requests per second
+  pub rate: u32,
+  // requests queued above the rate before rejecting, defaults to 0
+  pub burst: Option<u32>,
+  // serves burst requests immediately instead of pacing them
+  pub nodelay: Option<bool>,
+  // e.g. "X-Api-Key", defaults to the client ip
+  pub key_header: Option<String>,
+}
+
+// Struct that contains the concurrent connection limit of a service
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxConnLimit {
+  pub max_connections: u32,
+  // e.g. "X-Api-Key", defaults to the client ip
+  pub key_header: Option<String>,
+}
+
+// Struct that contains the auth service a service's requests are checked against
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxForwardAuth {
+  // name of the JinxService answering auth requests, 2xx allows and 401 or 403 denies
+  pub service: String,
+  // path on the auth service, defaults to "/"
+  pub path: Option<String>,
+  // auth response headers passed upstream, e.g. "X-User"
+  pub response_headers: Option<Vec<String>>,
+  // redirect on 401 with the original url as rd, e.g. "https://sso.example.com/login"
+  pub login_url: Option<String>,
+}
+
+// how nginx treats client certificates
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, std::cmp::PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JinxVerifyClient {
+  // rejects clients without a valid certificate, the default
+  On,
+  // lets clients without a certificate through, upstreams decide
+  Optional,
+}
+
+// Struct that contains the client certificate verification of an https service
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxClientAuth {
+  // host path of the pem CA bundle, bundled in the proxy image
+  pub ca_path: Option<String>,
+  // docker secret as name:id, like image_secrets
+  pub ca_secret: Option<String>,
+  pub verify: Option<JinxVerifyClient>,
+  // defaults to 1, a client certificate signed directly by the CA
+  pub verify_depth: Option<u32>,
+  // passes the client subject DN upstream, e.g. "X-Client-DN"
+  pub dn_header: Option<String>,
+}
+
+// Struct that contains a certificate used instead of letsencrypt, from host files or docker secrets
+#[derive(Debug, Deserialize, Serialize, Clone, Default, std::cmp::PartialEq)]
+pub struct JinxCustomCert {
+  // host paths of the pem encoded certificate chain and private key
+  pub cert_path: Option<String>,
+  pub key_path: Option<String>,
+  // docker secrets as name:id, like image_secrets
+  pub cert_secret: Option<String>,
+  pub key_secret: Option<String>,
+}
 
 #[derive(Debug, Deserialize, Serialize, Clone, std::cmp::PartialEq)]
 pub struct JinxService {
@@ -18,6 +242,42 @@ pub struct JinxService {
   pub published_port: Option<i64>,
   pub https_redirect: bool,
   pub https: bool,
+  pub compression: Option<TarCompression>,
+  pub mode: Option<JinxServiceMode>,
+  pub replicas: Option<i64>,
+  pub update_config: Option<JinxUpdateConfig>,
+  pub rollback_config: Option<JinxUpdateConfig>,
+  pub healthcheck: Option<JinxHealthcheck>,
+  pub resources: Option<JinxServiceResources>,
+  pub placement: Option<JinxPlacement>,
+  // additional domains served alongside domain
+  pub domain_aliases: Option<Vec<String>>,
+  // serve and certify www. variants of each domain, defaults to true
+  pub include_www: Option<bool>,
+  // paths routed to the service, defaults to "/"
+  pub routes: Option<Vec<JinxRoute>>,
+  // forward Upgrade and Connection headers, defaults to false
+  pub websocket: Option<bool>,
+  // nginx times such as "3600s" for long lived connections
+  pub proxy_read_timeout: Option<String>,
+  pub proxy_send_timeout: Option<String>,
+  // sent on https responses when set
+  pub hsts: Option<JinxHsts>,
+  // defaults to false
+  pub ocsp_stapling: Option<bool>,
+  // defaults to the letsencrypt certificate
+  pub custom_cert: Option<JinxCustomCert>,
+  // defaults to vip
+  pub endpoint_mode: Option<JinxEndpointMode>,
+  pub load_balancing: Option<JinxLoadBalancing>,
+  // rejected requests get a 429
+  pub rate_limit: Option<JinxRateLimit>,
+  pub conn_limit: Option<JinxConnLimit>,
+  // applies to every route without its own access
+  pub access: Option<JinxAccess>,
+  pub forward_auth: Option<JinxForwardAuth>,
+  // requires https and https_redirect
+  pub client_auth: Option<JinxClientAuth>,
 }
 
 impl Default for JinxService {
@@ -33,47 +293,363 @@ impl Default for JinxService {
       published_port: None,
       https_redirect: false,
       https: false,
+      compression: None,
+      mode: None,
+      replicas: None,
+      update_config: None,
+      rollback_config: None,
+      healthcheck: None,
+      resources: None,
+      placement: None,
+      domain_aliases: None,
+      include_www: None,
+      routes: None,
+      websocket: None,
+      proxy_read_timeout: None,
+      proxy_send_timeout: None,
+      hsts: None,
+      ocsp_stapling: None,
+      custom_cert: None,
+      endpoint_mode: None,
+      load_balancing: None,
+      rate_limit: None,
+      conn_limit: None,
+      access: None,
+      forward_auth: None,
+      client_auth: None,
     }
   }
 }
 
-// returns Option<JinxService>
-pub fn get_jinx_service() -> JinxService {
+impl JinxService {
+  // returns the domain followed by its aliases
+  pub fn get_domains(&self) -> Vec<String> {
+    let mut domains = vec![self.domain.to_string()];
+
+    for alias in self.domain_aliases.clone().unwrap_or_default() {
+      if !domains.contains(&alias) {
+        domains.push(alias);
+      }
+    }
+
+    domains
+  }
+
+  // returns every host name served, including www. variants unless disabled
+  pub fn get_server_names(&self) -> Vec<String> {
+    let mut server_names = vec![];
+
+    for domain in self.get_domains() {
+      let www_domain = format!("www.{}", domain);
+      // wildcards and www. aliases have no www. variant
+      let variant = !domain.starts_with("*.") && !domain.starts_with("www.");
+      if !server_names.contains(&domain) {
+        server_names.push(domain);
+      }
+      if self.include_www.unwrap_or(true) && variant && !server_names.contains(&www_domain) {
+        server_names.push(www_domain);
+      }
+    }
+
+    server_names
+  }
+
+  // returns the routes of the service, defaulting to every path
+  pub fn get_routes(&self) -> Vec<JinxRoute> {
+    match &self.routes {
+      Some(routes) if !routes.is_empty() => routes.clone(),
+      _ => vec![JinxRoute {
+        path: "/".to_string(),
+        ..Default::default()
+      }],
+    }
+  }
+
+  // returns the letsencrypt certificate name, matching the live/ directory nginx reads
+  pub fn get_cert_name(&self) -> String {
+    if let Some(base) = self.domain.strip_prefix("*.") {
+      // underscores keep it from clashing with a real host name
+      format!("_wildcard.{}", base)
+    } else if self.include_www.unwrap_or(true) {
+      format!("www.{}", self.domain)
+    } else {
+      self.domain.to_string()
+    }
+  }
+
+  // returns the proxy container paths of the certificate chain and private key
+  pub fn get_ssl_paths(&self) -> Result<(String, String), JinxError> {
+    let custom_cert = match &self.custom_cert {
+      Some(custom_cert) => custom_cert,
+      None => {
+        let live = format!("/etc/letsencrypt/live/{}", self.get_cert_name());
+        return Ok((format!("{}/fullchain.pem", live), format!("{}/privkey.pem", live)));
+      }
+    };
+
+    match (
+      &custom_cert.cert_path,
+      &custom_cert.key_path,
+      &custom_cert.cert_secret,
+      &custom_cert.key_secret,
+    ) {
+      (Some(_), Some(_), None, None) => Ok((
+        format!("/etc/jinx/certs/{}/fullchain.pem", self.name),
+        format!("/etc/jinx/certs/{}/privkey.pem", self.name),
+      )),
+      (None, None, Some(cert_secret), Some(key_secret)) => Ok((
+        format!("/run/secrets/{}", get_secret_name(cert_secret)),
+        format!("/run/secrets/{}", get_secret_name(key_secret)),
+      )),
+      _ => Err(JinxError::InvalidConfig(format!(
+        "custom_cert of {} needs either cert_path and key_path or cert_secret and key_secret",
+        self.name
+      ))),
+    }
+  }
+
+  // returns the name:id secrets of a custom certificate from docker secrets
+  pub fn get_cert_secrets(&self) -> Vec<String> {
+    match &self.custom_cert {
+      Some(JinxCustomCert {
+        cert_secret: Some(cert_secret),
+        key_secret: Some(key_secret),
+        ..
+      }) => vec![cert_secret.to_string(), key_secret.to_string()],
+      _ => vec![],
+    }
+  }
+
+  // returns the proxy container path of the client CA bundle, when clients present certificates
+  pub fn get_client_ca_path(&self) -> Result<Option<String>, JinxError> {
+    let client_auth = match &self.client_auth {
+      Some(client_auth) => client_auth,
+      None => return Ok(None),
+    };
+
+    // plain http would skip verification
+    if !self.https || !self.https_redirect {
+      return Err(JinxError::InvalidConfig(format!(
+        "client_auth of {} needs https and https_redirect",
+        self.name
+      )));
+    }
+
+    match (&client_auth.ca_path, &client_auth.ca_secret) {
+      (Some(_), None) => Ok(Some(format!("/etc/nginx/client_ca/{}.pem", self.name))),
+      (None, Some(ca_secret)) => Ok(Some(format!("/run/secrets/{}", get_secret_name(ca_secret)))),
+      _ => Err(JinxError::InvalidConfig(format!(
+        "client_auth of {} needs either ca_path or ca_secret",
+        self.name
+      ))),
+    }
+  }
+}
+
+// returns the name of a name:id secret
+fn get_secret_name(secret: &str) -> &str {
+  secret.split(':').next().unwrap_or(secret)
+}
+
+// returns JinxService parsed from jinx.json in the current directory
+pub fn get_jinx_service() -> Result<JinxService, JinxError> {
   // get current directory
-  let current_dir = env::current_dir().expect("[JINX] Failed to get current directory");
+  let current_dir = env::current_dir()?;
 
   // attempt to open jinx.json in current directory
   let jinx_path = format!("{}/jinx.json", current_dir.display());
-  let file = match File::open(jinx_path) {
-    Err(err) => log_exit!("[SERVICE] Failed to open jinx.json {}", err),
-    Ok(file) => file,
-  };
+  let file = File::open(&jinx_path)
+    .map_err(|err| io::Error::new(err.kind(), format!("Failed to open {}: {}", jinx_path, err)))?;
 
   // read the file
   let reader = BufReader::new(file);
 
-  // parse jinx.json into a JinxService
-  let service = match serde_json::from_reader(reader) {
-    Err(err) => log_exit!("[SERVICE] Failed to parse jinx.json {}", err),
-    Ok(file) => file,
-  };
+  // parse jinx.json into a JinxService, naming the file since jinx_conf.json fails the same way
+  let service = serde_json::from_reader(reader)
+    .map_err(|err| serde_json::Error::custom(format!("{}: {}", jinx_path, err)))?;
 
-  service
+  Ok(service)
 }
 
-pub fn get_jinx_proxy_service() -> JinxService {
-  let jinx_files = get_jinx_files();
-
+// returns the host:container volumes of jinx_proxy
+pub fn get_jinx_proxy_volumes(jinx_files: &JinxFiles) -> Vec<String> {
   let conf = format!("{}:/etc/letsencrypt", jinx_files.letsencrypt_conf);
   let www = format!("{}:/var/www/certbot", jinx_files.letsencrypt_www);
-  let volumes = vec![conf, www];
+  // mounted so config changes can be reloaded without replacing the container
+  let nginx_conf = format!("{}:/etc/nginx/nginx.conf", jinx_files.nginx_conf);
+  // custom certificates copied from host files, as a dir so reloads pick up new ones
+  let certs = format!("{}:/etc/jinx/certs", jinx_files.proxy_certs);
+  let htpasswd = format!("{}:/etc/nginx/htpasswd", jinx_files.htpasswd_dir);
+  let client_ca = format!("{}:/etc/nginx/client_ca", jinx_files.client_ca_dir);
+
+  vec![conf, www, nginx_conf, certs, htpasswd, client_ca]
+}
+
+pub fn get_jinx_proxy_service() -> Result<JinxService, JinxError> {
+  let jinx_files = get_jinx_files()?;
+  let jinx_conf = get_jinx_conf()?;
+
+  let volumes = get_jinx_proxy_volumes(&jinx_files);
+
+  // custom certificates of https services kept in secrets
+  let mut secrets = vec![];
+  for jinx_service in jinx_conf.jinx_services.iter().filter(|s| s.https) {
+    secrets.append(&mut jinx_service.get_cert_secrets());
+  }
+  // client CA bundles kept in secrets rather than the image
+  for jinx_service in jinx_conf.jinx_services.iter() {
+    if let Some(ca_secret) = jinx_service.client_auth.as_ref().and_then(|c| c.ca_secret.clone()) {
+      secrets.push(ca_secret);
+    }
+  }
+
+  // htpasswd files kept in secrets rather than the image
+  for htpasswd_secret in jinx_conf.htpasswd_secrets.clone().unwrap_or_default() {
+    secrets.push(htpasswd_secret.secret);
+  }
 
-  JinxService {
+  // services may share a secret
+  secrets.sort();
+  secrets.dedup();
+
+  Ok(JinxService {
     name: "jinx_proxy".to_string(),
     image_name: "jinx_proxy".to_string(),
     image_port: 80,
     image_volumes: Some(volumes),
+    image_secrets: Some(secrets),
     published_port: Some(80),
+    placement: jinx_conf.jinx_proxy_placement,
     ..Default::default()
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // returns a service of the domain and aliases
+  fn get_test_service(domain: &str, aliases: &[&str]) -> JinxService {
+    JinxService {
+      domain: domain.to_string(),
+      domain_aliases: Some(aliases.iter().map(|a| a.to_string()).collect()),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn lists_server_names_with_www_variants() {
+    let jinx_service = get_test_service(
+      "example.com",
+      &[
+        "example.org",
+        "example.com",
+        "www.example.com",
+        "*.example.net",
+      ],
+    );
+
+    assert_eq!(
+      jinx_service.get_server_names(),
+      vec![
+        "example.com",
+        "www.example.com",
+        "example.org",
+        "www.example.org",
+        "*.example.net"
+      ]
+    );
+  }
+
+  #[test]
+  fn lists_server_names_without_www_variants() {
+    let jinx_service = JinxService {
+      include_www: Some(false),
+      ..get_test_service("example.com", &["example.org"])
+    };
+
+    assert_eq!(
+      jinx_service.get_server_names(),
+      vec!["example.com", "example.org"]
+    );
+  }
+
+  #[test]
+  fn names_certificates_after_the_domain() {
+    assert_eq!(
+      get_test_service("example.com", &[]).get_cert_name(),
+      "www.example.com"
+    );
+    assert_eq!(
+      get_test_service("*.example.com", &[]).get_cert_name(),
+      "_wildcard.example.com"
+    );
+
+    let jinx_service = JinxService {
+      include_www: Some(false),
+      ..get_test_service("example.com", &[])
+    };
+    assert_eq!(jinx_service.get_cert_name(), "example.com");
+  }
+
+  #[test]
+  fn locates_client_ca_bundles() {
+    let mut jinx_service = JinxService {
+      name: "web".to_string(),
+      https: true,
+      https_redirect: true,
+      ..Default::default()
+    };
+    assert_eq!(jinx_service.get_client_ca_path().unwrap(), None);
+
+    jinx_service.client_auth = Some(JinxClientAuth {
+      ca_path: Some("/etc/ssl/ca.pem".to_string()),
+      ..Default::default()
+    });
+    assert_eq!(
+      jinx_service.get_client_ca_path().unwrap().as_deref(),
+      Some("/etc/nginx/client_ca/web.pem")
+    );
+
+    jinx_service.client_auth = Some(JinxClientAuth {
+      ca_secret: Some("web_ca:abc123".to_string()),
+      ..Default::default()
+    });
+    assert_eq!(
+      jinx_service.get_client_ca_path().unwrap().as_deref(),
+      Some("/run/secrets/web_ca")
+    );
+  }
+
+  #[test]
+  fn rejects_invalid_client_auth() {
+    let client_auths = [
+      JinxClientAuth::default(),
+      JinxClientAuth {
+        ca_path: Some("/etc/ssl/ca.pem".to_string()),
+        ca_secret: Some("web_ca:abc123".to_string()),
+        ..Default::default()
+      },
+    ];
+    for client_auth in client_auths {
+      let jinx_service = JinxService {
+        https: true,
+        https_redirect: true,
+        client_auth: Some(client_auth),
+        ..Default::default()
+      };
+      assert!(jinx_service.get_client_ca_path().is_err());
+    }
+
+    // plain http would skip verification
+    let jinx_service = JinxService {
+      https: true,
+      client_auth: Some(JinxClientAuth {
+        ca_path: Some("/etc/ssl/ca.pem".to_string()),
+        ..Default::default()
+      }),
+      ..Default::default()
+    };
+    assert!(jinx_service.get_client_ca_path().is_err());
   }
 }
@@ -1,119 +1,528 @@
+use flate2::write::GzEncoder;
+use serde_derive::{Deserialize, Serialize};
 use std::env;
 use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use tar::Builder;
 
-use super::log_exit;
+use crate::dockerignore::DockerIgnore;
+use crate::error::JinxError;
 use crate::file::get_jinx_files;
 use crate::service::get_jinx_proxy_service;
 use crate::service::JinxService;
 
-pub fn create_jinx_proxy_tar() {
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, std::cmp::PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionFormat {
+  Gzip,
+  Zstd,
+}
+
+impl CompressionFormat {
+  // returns the file extension used for the format
+  pub fn extension(&self) -> &'static str {
+    match self {
+      CompressionFormat::Gzip => "tar.gz",
+      CompressionFormat::Zstd => "tar.zst",
+    }
+  }
+
+  // returns the level used when none is configured
+  fn default_level(&self) -> u32 {
+    match self {
+      CompressionFormat::Gzip => 6,
+      CompressionFormat::Zstd => 3,
+    }
+  }
+}
+
+// Struct that contains the compression applied to the build context
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, std::cmp::PartialEq)]
+pub struct TarCompression {
+  pub format: CompressionFormat,
+  pub level: Option<u32>,
+}
+
+impl Default for TarCompression {
+  fn default() -> Self {
+    Self {
+      format: CompressionFormat::Gzip,
+      level: None,
+    }
+  }
+}
+
+impl TarCompression {
+  // returns the configured level, validated for the format
+  pub fn get_level(&self) -> Result<u32, JinxError> {
+    let level = self.level.unwrap_or_else(|| self.format.default_level());
+
+    let valid = match self.format {
+      CompressionFormat::Gzip => level <= 9,
+      #[cfg(feature = "zstd")]
+      CompressionFormat::Zstd => (1..=22).contains(&level),
+      #[cfg(not(feature = "zstd"))]
+      CompressionFormat::Zstd => return Err(zstd_unsupported()),
+    };
+    if !valid {
+      return Err(JinxError::InvalidConfig(format!(
+        "compression level {} is not valid for {:?}",
+        level, self.format
+      )));
+    }
+
+    Ok(level)
+  }
+}
+
+pub fn create_jinx_proxy_tar() -> Result<(), JinxError> {
   // get service
-  let jinx_service = get_jinx_proxy_service();
+  let jinx_service = get_jinx_proxy_service()?;
 
   // get jinx files
-  let jinx_files = get_jinx_files();
+  let jinx_files = get_jinx_files()?;
+
+  let excluded = get_jinx_proxy_excluded();
 
-  let excluded = vec!["jinx_conf.json".to_string(), ".jinx.tar.gz".to_string(),  "letsencrypt".to_string()];
+  _write_tar(&jinx_service, &excluded, Some(jinx_files.jinx_home))
+}
 
-  _write_tar(&jinx_service, &excluded, Some(jinx_files.jinx_home));
+// returns the patterns excluded from the jinx_proxy tar
+fn get_jinx_proxy_excluded() -> Vec<String> {
+  vec![
+    "jinx_conf.json".to_string(),
+    "jinx_htpasswd.json".to_string(),
+    "*.jinx.tar.*".to_string(),
+    "letsencrypt".to_string(),
+    "certs".to_string(),
+    "proxy_certs".to_string(),
+    "htpasswd".to_string(),
+    "client_ca".to_string(),
+  ]
 }
 
-pub fn get_jinx_proxy_tar() -> Vec<u8> {
+pub fn get_jinx_proxy_tar() -> Result<Vec<u8>, JinxError> {
   // get service
-  let jinx_service = get_jinx_proxy_service();
+  let jinx_service = get_jinx_proxy_service()?;
 
   get_tar(&jinx_service)
 }
 
-// returns a Vec<u8> of the tar.gz file
-pub fn get_tar(jinx_service: &JinxService) -> Vec<u8> {
+// returns a Vec<u8> of the compressed tar file, in whichever format was written
+pub fn get_tar(jinx_service: &JinxService) -> Result<Vec<u8>, JinxError> {
   // get jinx files
-  let jinx_files = get_jinx_files();
+  let jinx_files = get_jinx_files()?;
 
-  // create path
-  let tar_file_path = format!(
-    "{}/{}.jinx.tar.gz",
-    jinx_files.jinx_home, &jinx_service.name
-  );
+  // find the tar file written for the service
+  let tar_file_path = [CompressionFormat::Gzip, CompressionFormat::Zstd]
+    .iter()
+    .map(|format| get_tar_path(&jinx_files.jinx_home, jinx_service, *format))
+    .find(|path| Path::new(path).exists())
+    .unwrap_or_else(|| get_tar_path(&jinx_files.jinx_home, jinx_service, CompressionFormat::Gzip));
 
   // open tar file
-  let mut tar_file = File::open(&tar_file_path).expect("[TARGZ] Failed to open tar file");
+  let mut tar_file = File::open(&tar_file_path).map_err(JinxError::Tar)?;
   let mut tar_buffer = vec![];
 
   // read tar file
   tar_file
     .read_to_end(&mut tar_buffer)
-    .expect("[TARGZ] Failed to read tar file");
+    .map_err(JinxError::Tar)?;
 
-  tar_buffer
+  Ok(tar_buffer)
 }
 
-// creates a tar of the project
-pub fn write_tar(jinx_service: &JinxService, excluded: &Vec<String>, directory: Option<String>) {
-  _write_tar(&jinx_service, &excluded, directory);
+// returns the path of the tar file for the service and format
+fn get_tar_path(jinx_home: &str, jinx_service: &JinxService, format: CompressionFormat) -> String {
+  format!(
+    "{}/{}.jinx.{}",
+    jinx_home,
+    &jinx_service.name,
+    format.extension()
+  )
 }
 
-fn _write_tar(jinx_service: &JinxService, excluded: &Vec<String>, directory: Option<String>) {
-  // get current directory
-  let mut dir = env::current_dir().expect("[TARGZ] Failed to get current directory");
+// creates a tar of the project, skipping paths matched by the .dockerignore style excluded patterns
+pub fn write_tar(
+  jinx_service: &JinxService,
+  excluded: &[String],
+  directory: Option<String>,
+) -> Result<(), JinxError> {
+  _write_tar(jinx_service, excluded, directory)
+}
 
-  // or use provided directory
-  if directory.is_some() {
-    dir = PathBuf::from(directory.expect("Failed to get directory"));
-  }
+// returns a Vec<u8> of the compressed tar of the project, built in memory without a tar file
+pub fn build_tar(
+  jinx_service: &JinxService,
+  excluded: &[String],
+  directory: Option<String>,
+) -> Result<Vec<u8>, JinxError> {
+  _build_tar(vec![], jinx_service, excluded, directory)
+}
+
+// returns a Vec<u8> of the compressed jinx_proxy tar, built in memory without a tar file
+pub fn build_jinx_proxy_tar() -> Result<Vec<u8>, JinxError> {
+  // get service
+  let jinx_service = get_jinx_proxy_service()?;
 
   // get jinx files
-  let jinx_files = get_jinx_files();
+  let jinx_files = get_jinx_files()?;
 
-  // create paths
-  let tar_file_path = format!(
-    "{}/{}.jinx.tar.gz",
-    jinx_files.jinx_home, &jinx_service.name
-  );
+  let excluded = get_jinx_proxy_excluded();
+
+  build_tar(&jinx_service, &excluded, Some(jinx_files.jinx_home))
+}
+
+fn _write_tar(
+  jinx_service: &JinxService,
+  excluded: &[String],
+  directory: Option<String>,
+) -> Result<(), JinxError> {
+  // get compression
+  let compression = jinx_service.compression.unwrap_or_default();
+  compression.get_level()?;
+
+  // get jinx files
+  let jinx_files = get_jinx_files()?;
 
   // create files
-  let tar_file = File::create(&tar_file_path).expect("[TARGZ] Failed to create tar file");
-  let mut tar_builder = Builder::new(tar_file);
+  let tar_file_path = get_tar_path(&jinx_files.jinx_home, jinx_service, compression.format);
+  let tar_file = File::create(&tar_file_path).map_err(JinxError::Tar)?;
+
+  _build_tar(tar_file, jinx_service, excluded, directory)?;
+
+  // remove tar files left by other formats so get_tar finds the new one
+  for format in [CompressionFormat::Gzip, CompressionFormat::Zstd].iter() {
+    if *format == compression.format {
+      continue;
+    }
+    let stale_path = get_tar_path(&jinx_files.jinx_home, jinx_service, *format);
+    if Path::new(&stale_path).exists() {
+      fs::remove_file(&stale_path).map_err(JinxError::Tar)?;
+    }
+  }
+
+  Ok(())
+}
+
+// writes the compressed tar of the directory into the writer and returns it once finished
+fn _build_tar<W: Write>(
+  writer: W,
+  jinx_service: &JinxService,
+  excluded: &[String],
+  directory: Option<String>,
+) -> Result<W, JinxError> {
+  // use provided directory or the current directory
+  let dir = match directory {
+    Some(directory) => PathBuf::from(directory),
+    None => env::current_dir()?,
+  };
+
+  // parse excluded patterns
+  let dockerignore = DockerIgnore::new(excluded)?;
+
+  // get compression
+  let compression = jinx_service.compression.unwrap_or_default();
+  let level = compression.get_level()?;
+
+  match compression.format {
+    CompressionFormat::Gzip => {
+      let encoder = GzEncoder::new(writer, flate2::Compression::new(level));
+      let mut tar_builder = Builder::new(encoder);
+      tar_builder.follow_symlinks(false);
+      _append_dir(&mut tar_builder, &dir, &dir, &dockerignore)?;
+
+      // finish the tar, then the gzip stream
+      let encoder = tar_builder.into_inner().map_err(JinxError::Tar)?;
+      encoder.finish().map_err(JinxError::Tar)
+    }
+    #[cfg(feature = "zstd")]
+    CompressionFormat::Zstd => {
+      let encoder = zstd::Encoder::new(writer, level as i32).map_err(JinxError::Tar)?;
+      let mut tar_builder = Builder::new(encoder);
+      tar_builder.follow_symlinks(false);
+      _append_dir(&mut tar_builder, &dir, &dir, &dockerignore)?;
+
+      // finish the tar, then the zstd stream
+      let encoder = tar_builder.into_inner().map_err(JinxError::Tar)?;
+      encoder.finish().map_err(JinxError::Tar)
+    }
+    #[cfg(not(feature = "zstd"))]
+    CompressionFormat::Zstd => Err(zstd_unsupported()),
+  }
+}
+
+// returns the error for zstd compression without the zstd feature
+#[cfg(not(feature = "zstd"))]
+fn zstd_unsupported() -> JinxError {
+  JinxError::InvalidConfig("zstd compression requires the zstd feature".to_string())
+}
 
+// appends the contents of a directory to the tar, recursing into subdirectories
+fn _append_dir<W: Write>(
+  tar_builder: &mut Builder<W>,
+  root: &Path,
+  dir: &Path,
+  dockerignore: &DockerIgnore,
+) -> Result<(), JinxError> {
   // get files in directory
-  let paths = fs::read_dir(&dir).expect("Failed to read jinx directory");
+  let paths = fs::read_dir(dir)?;
 
   // iterate over files
-  'path: for path in paths {
-    let p = path.expect("Failed to get path");
+  for path in paths {
+    let p = path?;
     let file_path = p.path();
 
-    let file_name = match p.file_name().into_string() {
-      Err(_) => log_exit!("[TARGZ] Failed to convert file name"),
-      Ok(name) => name,
+    // get path relative to the context root
+    let relative_path = match file_path.strip_prefix(root).ok().and_then(|p| p.to_str()) {
+      None => {
+        return Err(JinxError::Tar(io::Error::new(
+          ErrorKind::InvalidData,
+          "Failed to convert file name",
+        )))
+      }
+      Some(name) => name.replace('\\', "/"),
     };
 
     // check excluded list
-    for ex in excluded.iter() {
-      if file_name.contains(ex) {
-        continue 'path;
+    let is_excluded = dockerignore.is_excluded(&relative_path);
+
+    // get file metadata, keeping symlinks as links like docker build does
+    let meta = fs::symlink_metadata(&file_path)?;
+
+    // check for directory, or file and symlink
+    if meta.is_dir() {
+      // excluded directories only need walking when a rule may re-include their contents
+      if is_excluded && !dockerignore.has_exceptions() {
+        continue;
+      }
+
+      if !is_excluded {
+        tar_builder
+          .append_dir(&relative_path, &file_path)
+          .map_err(JinxError::Tar)?;
       }
+
+      _append_dir(tar_builder, root, &file_path, dockerignore)?;
+    } else if !is_excluded {
+      tar_builder
+        .append_path_with_name(&file_path, &relative_path)
+        .map_err(JinxError::Tar)?;
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use flate2::read::GzDecoder;
+  use tar::Archive;
+
+  // returns an empty directory under the system temp dir
+  fn get_test_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("jinx-targz-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  // returns a context with a Dockerfile and a source file
+  fn get_test_context(name: &str) -> PathBuf {
+    let dir = get_test_dir(name);
+    fs::write(dir.join("Dockerfile"), "FROM scratch\n").unwrap();
+    fs::create_dir(dir.join("src")).unwrap();
+    fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+    dir
+  }
+
+  // returns the sorted paths of an uncompressed tar
+  fn get_entries(tar: &[u8]) -> Vec<String> {
+    let mut archive = Archive::new(tar);
+    let mut entries: Vec<String> = archive
+      .entries()
+      .unwrap()
+      .map(|e| e.unwrap().path().unwrap().to_string_lossy().to_string())
+      .collect();
+    entries.sort();
+    entries
+  }
+
+  // returns the service compressing with the format and level
+  fn get_test_service(format: CompressionFormat, level: Option<u32>) -> JinxService {
+    JinxService {
+      compression: Some(TarCompression { format, level }),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn builds_finished_gzip_archives() {
+    let dir = get_test_context("gzip");
+    let directory = Some(dir.to_string_lossy().to_string());
+
+    let jinx_service = get_test_service(CompressionFormat::Gzip, Some(9));
+    let compressed = _build_tar(vec![], &jinx_service, &[], directory).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    // a truncated stream fails the gzip trailer check
+    assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+    let mut tar = vec![];
+    GzDecoder::new(&compressed[..])
+      .read_to_end(&mut tar)
+      .unwrap();
+    assert_eq!(get_entries(&tar), vec!["Dockerfile", "src", "src/main.rs"]);
+  }
+
+  #[cfg(feature = "zstd")]
+  #[test]
+  fn builds_finished_zstd_archives() {
+    let dir = get_test_context("zstd");
+    let directory = Some(dir.to_string_lossy().to_string());
+
+    let jinx_service = get_test_service(CompressionFormat::Zstd, None);
+    let compressed = _build_tar(vec![], &jinx_service, &[], directory).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let tar = zstd::stream::decode_all(&compressed[..]).unwrap();
+    assert_eq!(get_entries(&tar), vec!["Dockerfile", "src", "src/main.rs"]);
+  }
+
+  #[test]
+  fn rejects_invalid_compression_levels() {
+    assert!(TarCompression::default().get_level().is_ok());
+    assert!(get_test_service(CompressionFormat::Gzip, Some(10))
+      .compression
+      .unwrap()
+      .get_level()
+      .is_err());
+
+    #[cfg(feature = "zstd")]
+    for level in [0, 23] {
+      let compression = TarCompression {
+        format: CompressionFormat::Zstd,
+        level: Some(level),
+      };
+      assert!(compression.get_level().is_err());
     }
 
-    // get file metadata
-    let meta = match p.metadata() {
-      Err(_err) => log_exit!("[TARGZ] Failed to get file metadata"),
-      Ok(meta) => meta,
+    // zstd needs the zstd feature
+    #[cfg(not(feature = "zstd"))]
+    assert!(get_test_service(CompressionFormat::Zstd, None)
+      .compression
+      .unwrap()
+      .get_level()
+      .is_err());
+  }
+
+  #[test]
+  fn builds_contexts_in_memory() {
+    let dir = get_test_context("memory");
+    let directory = Some(dir.to_string_lossy().to_string());
+
+    let jinx_service = JinxService {
+      name: "memory".to_string(),
+      ..Default::default()
     };
+    let compressed = build_tar(&jinx_service, &[], directory).unwrap();
 
-    // check for directory or file
-    if meta.is_dir() {
-      tar_builder
-        .append_dir_all(&file_name, &file_name)
-        .expect("[TARGZ] Failed to append to tar file");
-    } else {
-      tar_builder
-        .append_path_with_name(&file_path, &file_name)
-        .expect("TARGZ] Failed to append to tar file");
+    // nothing is written next to the context
+    let mut files: Vec<String> = fs::read_dir(&dir)
+      .unwrap()
+      .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+      .collect();
+    files.sort();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(files, vec!["Dockerfile", "src"]);
+
+    let mut tar = vec![];
+    GzDecoder::new(&compressed[..])
+      .read_to_end(&mut tar)
+      .unwrap();
+    let mut archive = Archive::new(&tar[..]);
+    for entry in archive.entries().unwrap() {
+      let mut entry = entry.unwrap();
+      if entry.path().unwrap().to_str() == Some("Dockerfile") {
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "FROM scratch\n");
+      }
     }
+    assert_eq!(get_entries(&tar), vec!["Dockerfile", "src", "src/main.rs"]);
+  }
+
+  #[test]
+  fn skips_excluded_paths_and_keeps_exceptions() {
+    let dir = get_test_context("excluded");
+    fs::create_dir_all(dir.join("node_modules/left-pad")).unwrap();
+    fs::write(dir.join("node_modules/left-pad/index.js"), "").unwrap();
+    fs::create_dir(dir.join("docs")).unwrap();
+    fs::write(dir.join("docs/README.md"), "").unwrap();
+    fs::write(dir.join("docs/notes.md"), "").unwrap();
+    fs::write(dir.join(".env"), "").unwrap();
+    let directory = Some(dir.to_string_lossy().to_string());
+
+    let excluded: Vec<String> = ["node_modules", "docs", "!docs/README.md", ".env"]
+      .iter()
+      .map(|e| e.to_string())
+      .collect();
+    let compressed = build_tar(&JinxService::default(), &excluded, directory).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let mut tar = vec![];
+    GzDecoder::new(&compressed[..])
+      .read_to_end(&mut tar)
+      .unwrap();
+    assert_eq!(
+      get_entries(&tar),
+      vec!["Dockerfile", "docs/README.md", "src", "src/main.rs"]
+    );
+  }
+
+  #[test]
+  fn keeps_symlinks_as_links() {
+    let dir = get_test_context("symlinks");
+    std::os::unix::fs::symlink("/etc/hostname", dir.join("hostname")).unwrap();
+    // a loop would recurse forever if followed
+    std::os::unix::fs::symlink(".", dir.join("src/loop")).unwrap();
+    let directory = Some(dir.to_string_lossy().to_string());
+
+    let compressed = build_tar(&JinxService::default(), &[], directory).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let mut tar = vec![];
+    GzDecoder::new(&compressed[..])
+      .read_to_end(&mut tar)
+      .unwrap();
+    let mut links = vec![];
+    let mut archive = Archive::new(&tar[..]);
+    for entry in archive.entries().unwrap() {
+      let entry = entry.unwrap();
+      if entry.header().entry_type().is_symlink() {
+        links.push((
+          entry.path().unwrap().to_string_lossy().to_string(),
+          entry
+            .link_name()
+            .unwrap()
+            .unwrap()
+            .to_string_lossy()
+            .to_string(),
+        ));
+      }
+    }
+    links.sort();
+
+    assert_eq!(
+      links,
+      vec![
+        ("hostname".to_string(), "/etc/hostname".to_string()),
+        ("src/loop".to_string(), ".".to_string())
+      ]
+    );
   }
 }
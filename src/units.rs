@@ -0,0 +1,139 @@
+use crate::error::JinxError;
+
+// returns the number of nanoseconds in a duration such as "90s", "1m30s", or "500ms"
+pub fn parse_duration(duration: &str) -> Result<i64, JinxError> {
+    let invalid = || JinxError::InvalidConfig(format!("duration {} is not valid", duration));
+
+    let duration = duration.trim();
+    if duration.is_empty() {
+        return Err(invalid());
+    }
+    if duration == "0" {
+        return Ok(0);
+    }
+
+    let mut total: i64 = 0;
+    let mut rest = duration;
+    while !rest.is_empty() {
+        // read the number
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .ok_or_else(invalid)?;
+        let number: f64 = rest[..number_len].parse().map_err(|_| invalid())?;
+        rest = &rest[number_len..];
+
+        // read the unit
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let nanos_per_unit = match &rest[..unit_len] {
+            "ns" => 1.0,
+            "us" | "µs" => 1e3,
+            "ms" => 1e6,
+            "s" => 1e9,
+            "m" => 60e9,
+            "h" => 3600e9,
+            _ => return Err(invalid()),
+        };
+        rest = &rest[unit_len..];
+
+        total += (number * nanos_per_unit) as i64;
+    }
+
+    Ok(total)
+}
+
+// returns the number of nano cpus in a cpu count such as "0.5" or "2"
+pub fn parse_cpus(cpus: &str) -> Result<i64, JinxError> {
+    let invalid = || JinxError::InvalidConfig(format!("cpus {} is not valid", cpus));
+
+    let count: f64 = cpus.trim().parse().map_err(|_| invalid())?;
+    if !count.is_finite() || count <= 0.0 {
+        return Err(invalid());
+    }
+
+    let nano_cpus = (count * 1e9).round() as i64;
+    if nano_cpus == 0 {
+        return Err(invalid());
+    }
+
+    Ok(nano_cpus)
+}
+
+// returns the number of bytes in a memory size such as "512m", "1.5g", or "1048576"
+pub fn parse_memory(memory: &str) -> Result<i64, JinxError> {
+    let invalid = || JinxError::InvalidConfig(format!("memory {} is not valid", memory));
+
+    // units are binary, matching the docker cli
+    let lower = memory.trim().to_lowercase();
+    let number = lower.strip_suffix('b').unwrap_or(&lower);
+    let (number, bytes_per_unit) = match number.chars().last() {
+        Some('k') => (&number[..number.len() - 1], 1024.0),
+        Some('m') => (&number[..number.len() - 1], 1024.0 * 1024.0),
+        Some('g') => (&number[..number.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        Some('t') => (&number[..number.len() - 1], 1024.0 * 1024.0 * 1024.0 * 1024.0),
+        _ => (number, 1.0),
+    };
+
+    let size: f64 = number.trim().parse().map_err(|_| invalid())?;
+    if !size.is_finite() || size <= 0.0 {
+        return Err(invalid());
+    }
+
+    let bytes = (size * bytes_per_unit) as i64;
+    if bytes == 0 {
+        return Err(invalid());
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("0").unwrap(), 0);
+        assert_eq!(parse_duration("90s").unwrap(), 90_000_000_000);
+        assert_eq!(parse_duration("1m30s").unwrap(), 90_000_000_000);
+        assert_eq!(parse_duration("500ms").unwrap(), 500_000_000);
+        assert_eq!(parse_duration("1.5h").unwrap(), 5_400_000_000_000);
+        assert_eq!(parse_duration("250us").unwrap(), 250_000);
+        assert_eq!(parse_duration("10ns").unwrap(), 10);
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for duration in ["", "  ", "90", "s", "1d", "1m30", "abc", "1..5s"] {
+            assert!(parse_duration(duration).is_err(), "{} parsed", duration);
+        }
+    }
+
+    #[test]
+    fn parses_cpus() {
+        assert_eq!(parse_cpus("0.5").unwrap(), 500_000_000);
+        assert_eq!(parse_cpus("2").unwrap(), 2_000_000_000);
+
+        for cpus in ["", "0", "-1", "abc", "0.0000000001"] {
+            assert!(parse_cpus(cpus).is_err(), "{} parsed", cpus);
+        }
+    }
+
+    #[test]
+    fn parses_memory() {
+        assert_eq!(parse_memory("512m").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_memory("1.5g").unwrap(), 1536 * 1024 * 1024);
+        assert_eq!(parse_memory("64K").unwrap(), 64 * 1024);
+        assert_eq!(parse_memory("512mb").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_memory("1048576").unwrap(), 1_048_576);
+        assert_eq!(parse_memory("1t").unwrap(), 1024 * 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn rejects_invalid_memory() {
+        for memory in ["", "m", "0", "-1m", "512bb", "512x", "abc"] {
+            assert!(parse_memory(memory).is_err(), "{} parsed", memory);
+        }
+    }
+}